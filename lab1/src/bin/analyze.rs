@@ -0,0 +1,501 @@
+//! Rebuilds the global history of a run from the event logs written with `--log jsonl` and checks
+//! it for mutual exclusion between neighbours, exclusive fork ownership and starvation, and
+//! reports how long philosophers waited for a meal and how long requests waited for their fork.
+//! Runs with bottle events are drinking philosophers, whose meals are drinking sessions: those
+//! may overlap between neighbours and hold no forks, so only the transfers are checked for them.
+//!
+//! usage: analyze [--ranks N] [--topology GRAPH] [--starvation-bound SECS] [--skew-us US] FILE...
+
+use lab1::{ event::EventKind, topology::{ Graph, Topology }, ForkId, ForkState };
+use std::{ collections::{ BTreeMap, HashMap }, fs, process };
+
+const USAGE: &str = "usage: analyze [--ranks N] [--topology GRAPH] [--starvation-bound SECS] [--skew-us US] FILE...";
+
+#[derive(Debug)]
+struct Record {
+    rank: i32,
+    time_us: u64,
+    lamport: Option<u64>,
+    kind: EventKind,
+    fork: Option<ForkId>,
+    peer: Option<i32>,
+    before: Option<ForkState>,
+    waited_us: Option<u64>,
+}
+
+/// Splits an object body at the commas that separate its fields, skipping those inside arrays.
+fn split_fields(body: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in body.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                fields.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&body[start..]);
+    fields
+}
+
+/// Parses the flat objects written by `JsonLinesSink`. Strings never contain quotes or commas, so
+/// there is no need for a general JSON parser.
+fn parse_record(line: &str) -> Result<Record, String> {
+    let body = line.trim().strip_prefix('{').and_then(|line| line.strip_suffix('}')).ok_or("not a JSON object")?;
+    let mut fields = HashMap::new();
+
+    for field in split_fields(body) {
+        let (key, value) = field.split_once(':').ok_or(format!("malformed field => {}", field))?;
+        let value = value.trim();
+        let value = if value == "null" { None } else { Some(value.trim_matches('"').to_string()) };
+        fields.insert(key.trim().trim_matches('"').to_string(), value);
+    }
+
+    let mut take = |key: &str| fields.remove(key).flatten();
+    let number = |key: &str, value: Option<String>| -> Result<u64, String> {
+        value.ok_or(format!("missing {}", key))?.parse().map_err(|_| format!("invalid {}", key))
+    };
+
+    Ok(Record {
+        rank: number("rank", take("rank"))? as i32,
+        time_us: number("time_us", take("time_us"))?,
+        lamport: take("lamport").map(|lamport| number("lamport", Some(lamport))).transpose()?,
+        kind: take("kind").ok_or("missing kind")?.parse()?,
+        fork: take("fork").map(|fork| fork.parse().map_err(|_| "invalid fork")).transpose()?,
+        peer: take("peer").map(|peer| peer.parse().map_err(|_| "invalid peer")).transpose()?,
+        before: take("before").map(|before| before.parse()).transpose()?,
+        // Logs from before requests were timed have no `waited_us` at all.
+        waited_us: take("waited_us").map(|waited| number("waited_us", Some(waited))).transpose()?,
+    })
+}
+
+struct Options {
+    ranks: Option<i32>,
+    topology: Topology,
+    starvation_bound_us: u64,
+    skew_us: u64,
+    files: Vec<String>,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut options = Options { ranks: None, topology: Topology::Ring, starvation_bound_us: 30_000_000, skew_us: 1_000, files: Vec::new() };
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--ranks" => options.ranks = Some(value()?.parse().map_err(|_| "invalid rank count")?),
+                "--topology" => options.topology = value()?.parse()?,
+                "--starvation-bound" => {
+                    let secs: f64 = value()?.parse().map_err(|_| "invalid starvation bound")?;
+                    options.starvation_bound_us = (secs * 1e6) as u64;
+                }
+                "--skew-us" => options.skew_us = value()?.parse().map_err(|_| "invalid skew")?,
+                _ if arg.starts_with("--") => return Err(format!("unknown argument => {}", arg)),
+                _ => options.files.push(arg),
+            }
+        }
+
+        if options.files.is_empty() {
+            return Err("no event logs given".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Default)]
+struct RankHistory {
+    meals: u32,
+    waits_us: Vec<u64>,
+    /// How long each request we answered waited for the fork.
+    releases_us: Vec<u64>,
+    eating: Vec<(u64, u64)>,
+    still_hungry_us: Option<u64>,
+    /// Ownership of the fork shared with each neighbour at the start of the run, from `fork_init`
+    /// or else the first event that mentions the fork.
+    initial: BTreeMap<i32, bool>,
+    given: BTreeMap<i32, Vec<Transfer>>,
+    received: BTreeMap<i32, Vec<Transfer>>,
+    bottles_given: BTreeMap<i32, Vec<Transfer>>,
+    bottles_received: BTreeMap<i32, Vec<Transfer>>,
+}
+
+impl RankHistory {
+    fn given_to(&self, peer: i32) -> &[Transfer] {
+        self.given.get(&peer).map_or(&[], Vec::as_slice)
+    }
+
+    fn received_from(&self, peer: i32) -> &[Transfer] {
+        self.received.get(&peer).map_or(&[], Vec::as_slice)
+    }
+
+    fn bottles_given_to(&self, peer: i32) -> &[Transfer] {
+        self.bottles_given.get(&peer).map_or(&[], Vec::as_slice)
+    }
+
+    fn bottles_received_from(&self, peer: i32) -> &[Transfer] {
+        self.bottles_received.get(&peer).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Transfer {
+    time_us: u64,
+    lamport: Option<u64>,
+}
+
+fn is_bottle(kind: EventKind) -> bool {
+    matches!(kind, EventKind::BottleRequested | EventKind::BottleReceived | EventKind::BottleGiven)
+}
+
+fn replay(rank: i32, graph: &Graph, records: &[Record], drinking: bool, violations: &mut Vec<String>) -> RankHistory {
+    let mut history = RankHistory::default();
+    // Only fork events about a fork that `rank` really shares with that peer are replayed.
+    let mut shared = Vec::new();
+    for record in records {
+        if let (Some(fork), Some(peer)) = (record.fork, record.peer) {
+            if graph.fork(rank, peer) == Some(fork) {
+                shared.push((record, peer));
+            } else {
+                violations.push(format!("[{}] exchanged fork {} with [{}] at {}us, which it does not share with it", rank, fork, peer, record.time_us));
+            }
+        }
+    }
+    for &(record, peer) in shared.iter().filter(|(record, _)| !is_bottle(record.kind)) {
+        if let Some(before) = record.before {
+            if record.kind == EventKind::ForkInit {
+                history.initial.insert(peer, before != ForkState::MISSING);
+            } else {
+                history.initial.entry(peer).or_insert(before != ForkState::MISSING);
+            }
+        }
+    }
+
+    let mut holding: BTreeMap<i32, bool> = graph.neighbours(rank).into_iter()
+        .map(|neighbour| (neighbour, history.initial.get(&neighbour).copied().unwrap_or(false)))
+        .collect();
+    let mut hungry_since = None;
+    let mut eating_since = None;
+
+    for record in records {
+        let t = record.time_us;
+        let peer = record.peer.filter(|&peer| record.fork.is_some() && graph.fork(rank, peer) == record.fork);
+
+        match (record.kind, peer) {
+            (EventKind::ThinkEnd, _) => hungry_since = Some(t),
+            // `dine` may stop right after thinking, and then waits for the others without being hungry.
+            (EventKind::Done | EventKind::Leave, _) => hungry_since = None,
+            (EventKind::EatStart, _) => {
+                for (neighbour, _) in holding.iter().filter(|(_, &held)| !held && !drinking) {
+                    violations.push(format!("[{}] started eating at {}us without the fork it shares with [{}]", rank, t, neighbour));
+                }
+                if let Some(since) = hungry_since.take() {
+                    history.waits_us.push(t - since);
+                }
+                history.meals += 1;
+                eating_since = Some(t);
+            }
+            (EventKind::EatEnd, _) => {
+                if let Some(since) = eating_since.take() {
+                    history.eating.push((since, t));
+                }
+            }
+            (EventKind::ForkGiven, Some(peer)) => {
+                if !holding[&peer] {
+                    violations.push(format!("[{}] gave away the fork it shares with [{}] at {}us without holding it", rank, peer, t));
+                }
+                if eating_since.is_some() && !drinking {
+                    violations.push(format!("[{}] gave away the fork it shares with [{}] at {}us while eating", rank, peer, t));
+                }
+                holding.insert(peer, false);
+                history.releases_us.extend(record.waited_us);
+                history.given.entry(peer).or_default().push(Transfer { time_us: t, lamport: record.lamport });
+            }
+            (EventKind::ForkReceived, Some(peer)) => {
+                if holding[&peer] {
+                    violations.push(format!("[{}] received the fork it shares with [{}] at {}us while already holding it", rank, peer, t));
+                }
+                holding.insert(peer, true);
+                history.received.entry(peer).or_default().push(Transfer { time_us: t, lamport: record.lamport });
+            }
+            (EventKind::BottleGiven, Some(peer)) => history.bottles_given.entry(peer).or_default().push(Transfer { time_us: t, lamport: record.lamport }),
+            (EventKind::BottleReceived, Some(peer)) => history.bottles_received.entry(peer).or_default().push(Transfer { time_us: t, lamport: record.lamport }),
+            _ => {}
+        }
+    }
+
+    if let (Some(since), Some(last)) = (hungry_since, records.last()) {
+        history.still_hungry_us = Some(last.time_us - since);
+    }
+    history
+}
+
+/// Every transfer of `item`, a fork or a bottle, has to be received by the other
+/// endpoint after it was given, and at most one transfer may still be in flight when the logs end.
+/// Lamport clocks decide "after" exactly; logs without them fall back to wall-clock time within
+/// the allowed skew.
+fn check_transfers(item: &str, from: i32, to: i32, given: &[Transfer], received: &[Transfer], skew_us: u64, violations: &mut Vec<String>) {
+    if received.len() > given.len() || given.len() - received.len() > 1 {
+        violations.push(format!(
+            "{} was given {} times by [{}] but received {} times by [{}]",
+            item, given.len(), from, received.len(), to
+        ));
+    }
+    for (given, received) in given.iter().zip(received) {
+        let out_of_order = match (given.lamport, received.lamport) {
+            (Some(given), Some(received)) => received <= given,
+            _ => received.time_us + skew_us < given.time_us,
+        };
+        if out_of_order {
+            violations.push(format!(
+                "{} reached [{}] at {}us before [{}] gave it away at {}us",
+                item, to, received.time_us, from, given.time_us
+            ));
+        }
+    }
+}
+
+/// Replays every rank and checks the table as a whole: who owned each fork at the start, every
+/// transfer, and neighbours eating at the same time. Waits are left to the caller.
+fn analyze(graph: &Graph, records: &BTreeMap<i32, Vec<Record>>, skew_us: u64) -> (BTreeMap<i32, RankHistory>, Vec<String>) {
+    let drinking = records.values().flatten().any(|record| is_bottle(record.kind));
+    let mut violations = Vec::new();
+    let histories: BTreeMap<i32, RankHistory> = (0..graph.size())
+        .map(|rank| (rank, replay(rank, graph, records.get(&rank).map_or(&[][..], |records| records), drinking, &mut violations)))
+        .collect();
+
+    for (&(rank, neighbour), fork) in graph.edges().iter().zip(0..) {
+        let (mine, theirs) = (&histories[&rank], &histories[&neighbour]);
+
+        match (mine.initial.get(&neighbour), theirs.initial.get(&rank)) {
+            (Some(true), Some(true)) => violations.push(format!("fork {} started out owned by both [{}] and [{}]", fork, rank, neighbour)),
+            (Some(false), Some(false)) => violations.push(format!("fork {} started out owned by neither [{}] nor [{}]", fork, rank, neighbour)),
+            _ => {}
+        }
+        let (fork_name, bottle_name) = (format!("fork {}", fork), format!("bottle {}", fork));
+        check_transfers(&fork_name, rank, neighbour, mine.given_to(neighbour), theirs.received_from(rank), skew_us, &mut violations);
+        check_transfers(&fork_name, neighbour, rank, theirs.given_to(rank), mine.received_from(neighbour), skew_us, &mut violations);
+        if drinking {
+            check_transfers(&bottle_name, rank, neighbour, mine.bottles_given_to(neighbour), theirs.bottles_received_from(rank), skew_us, &mut violations);
+            check_transfers(&bottle_name, neighbour, rank, theirs.bottles_given_to(rank), mine.bottles_received_from(neighbour), skew_us, &mut violations);
+            continue;
+        }
+
+        for &(start, end) in &mine.eating {
+            for &(other_start, other_end) in &theirs.eating {
+                if start + skew_us < other_end && other_start + skew_us < end {
+                    violations.push(format!(
+                        "[{}] ate at {}..{}us while its neighbour [{}] ate at {}..{}us",
+                        rank, start, end, neighbour, other_start, other_end
+                    ));
+                }
+            }
+        }
+    }
+    (histories, violations)
+}
+
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let index = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[index.clamp(1, sorted.len()) - 1]
+}
+
+fn ms(us: u64) -> String {
+    format!("{:.1}ms", us as f64 / 1000.0)
+}
+
+fn main() {
+    let options = Options::from_args().unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    let mut records: BTreeMap<i32, Vec<Record>> = BTreeMap::new();
+    for path in &options.files {
+        let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("cannot read {} => {}", path, err);
+            process::exit(2);
+        });
+        for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match parse_record(line) {
+                Ok(record) => records.entry(record.rank).or_default().push(record),
+                Err(err) => {
+                    eprintln!("{}:{}: {}", path, number + 1, err);
+                    process::exit(2);
+                }
+            }
+        }
+    }
+    for history in records.values_mut() {
+        history.sort_by_key(|record| record.time_us);
+    }
+
+    let ranks = options.ranks.unwrap_or_else(|| records.keys().last().map_or(0, |rank| rank + 1));
+    let graph = options.topology.graph(ranks).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+    let (histories, mut violations) = analyze(&graph, &records, options.skew_us);
+
+    let mut all_waits = Vec::new();
+    println!("{:>6} {:>7} {:>10} {:>10} {:>10} {:>10}", "rank", "meals", "wait p50", "wait p90", "wait p99", "wait max");
+    for (rank, history) in &histories {
+        let mut waits = history.waits_us.clone();
+        waits.sort_unstable();
+        println!(
+            "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10}",
+            rank, history.meals, ms(percentile(&waits, 50.0)), ms(percentile(&waits, 90.0)),
+            ms(percentile(&waits, 99.0)), ms(waits.last().copied().unwrap_or(0))
+        );
+
+        for &wait in waits.iter().filter(|&&wait| wait > options.starvation_bound_us) {
+            violations.push(format!("[{}] waited {} for a meal, over the starvation bound", rank, ms(wait)));
+        }
+        if let Some(wait) = history.still_hungry_us.filter(|&wait| wait > options.starvation_bound_us) {
+            violations.push(format!("[{}] was still hungry after {} when its log ended", rank, ms(wait)));
+        }
+        all_waits.extend(waits);
+    }
+    all_waits.sort_unstable();
+    println!(
+        "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10}",
+        "all", histories.values().map(|history| history.meals).sum::<u32>(), ms(percentile(&all_waits, 50.0)),
+        ms(percentile(&all_waits, 90.0)), ms(percentile(&all_waits, 99.0)), ms(all_waits.last().copied().unwrap_or(0))
+    );
+
+    // From the moment a request arrived until the fork went out, as measured by its holder.
+    let mut all_releases = Vec::new();
+    println!();
+    println!("{:>6} {:>7} {:>10} {:>10} {:>10} {:>10}", "rank", "given", "hold p50", "hold p90", "hold p99", "hold max");
+    for (rank, history) in &histories {
+        let mut releases = history.releases_us.clone();
+        releases.sort_unstable();
+        println!(
+            "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10}",
+            rank, releases.len(), ms(percentile(&releases, 50.0)), ms(percentile(&releases, 90.0)),
+            ms(percentile(&releases, 99.0)), ms(releases.last().copied().unwrap_or(0))
+        );
+        all_releases.extend(releases);
+    }
+    all_releases.sort_unstable();
+    println!(
+        "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10}",
+        "all", all_releases.len(), ms(percentile(&all_releases, 50.0)), ms(percentile(&all_releases, 90.0)),
+        ms(percentile(&all_releases, 99.0)), ms(all_releases.last().copied().unwrap_or(0))
+    );
+
+    println!();
+    if violations.is_empty() {
+        println!("no violations found");
+    } else {
+        println!("{} violation(s):", violations.len());
+        for violation in &violations {
+            println!("  {}", violation);
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(lines: &[&str]) -> Vec<Record> {
+        lines.iter().map(|line| parse_record(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn only_a_log_that_ends_hungry_counts_as_still_hungry() {
+        let graph = Topology::Ring.graph(2).unwrap();
+        let stopped = records(&[
+            r#"{"rank":0,"time_us":0,"kind":"think_start"}"#,
+            r#"{"rank":0,"time_us":100,"kind":"think_end"}"#,
+            r#"{"rank":0,"time_us":100,"kind":"done"}"#,
+            r#"{"rank":0,"time_us":90000000,"kind":"leave"}"#,
+        ]);
+        let mut violations = Vec::new();
+        assert_eq!(replay(0, &graph, &stopped, false, &mut violations).still_hungry_us, None);
+        assert!(violations.is_empty(), "{:?}", violations);
+
+        let hungry = records(&[
+            r#"{"rank":0,"time_us":0,"kind":"think_start"}"#,
+            r#"{"rank":0,"time_us":100,"kind":"think_end"}"#,
+            r#"{"rank":0,"time_us":5000,"kind":"request_sent","fork":0,"peer":1,"before":"missing","after":"missing"}"#,
+        ]);
+        assert_eq!(replay(0, &graph, &hungry, false, &mut violations).still_hungry_us, Some(4900));
+    }
+
+    /// [0] starts with the fork, eats, and hands it to [1] on request, who then eats too.
+    const CLEAN: [&str; 12] = [
+        r#"{"rank":0,"time_us":0,"lamport":1,"kind":"fork_init","fork":0,"peer":1,"before":"dirty","after":"dirty"}"#,
+        r#"{"rank":1,"time_us":0,"lamport":1,"kind":"fork_init","fork":0,"peer":0,"before":"missing","after":"missing"}"#,
+        r#"{"rank":0,"time_us":10,"lamport":2,"kind":"think_end"}"#,
+        r#"{"rank":1,"time_us":15,"lamport":2,"kind":"think_end"}"#,
+        r#"{"rank":1,"time_us":16,"lamport":3,"kind":"request_sent","fork":0,"peer":0,"before":"missing","after":"missing"}"#,
+        r#"{"rank":0,"time_us":17,"lamport":4,"kind":"request_received","fork":0,"peer":1,"before":"dirty","after":"dirty"}"#,
+        r#"{"rank":0,"time_us":20,"lamport":5,"kind":"eat_start"}"#,
+        r#"{"rank":0,"time_us":30,"lamport":6,"kind":"eat_end"}"#,
+        r#"{"rank":0,"time_us":31,"lamport":7,"kind":"fork_given","fork":0,"peer":1,"before":"dirty","after":"missing","waited_us":14}"#,
+        r#"{"rank":1,"time_us":32,"lamport":8,"kind":"fork_received","fork":0,"peer":0,"before":"missing","after":"clean"}"#,
+        r#"{"rank":1,"time_us":33,"lamport":9,"kind":"eat_start"}"#,
+        r#"{"rank":1,"time_us":40,"lamport":10,"kind":"eat_end"}"#,
+    ];
+
+    /// The violations of a ring of two, with no allowance for clock skew.
+    fn violations(lines: &[&str]) -> Vec<String> {
+        let mut table: BTreeMap<i32, Vec<Record>> = BTreeMap::new();
+        for record in records(lines) {
+            table.entry(record.rank).or_default().push(record);
+        }
+        for history in table.values_mut() {
+            history.sort_by_key(|record| record.time_us);
+        }
+        analyze(&Topology::Ring.graph(2).unwrap(), &table, 0).1
+    }
+
+    /// The violations of `CLEAN` with line `index` replaced.
+    fn altered(index: usize, line: &str) -> Vec<String> {
+        let mut lines = CLEAN.to_vec();
+        lines[index] = line;
+        violations(&lines)
+    }
+
+    fn reports(violations: &[String], expected: &str) -> bool {
+        violations.iter().any(|violation| violation.contains(expected))
+    }
+
+    #[test]
+    fn a_clean_log_has_no_violations() {
+        assert!(violations(&CLEAN).is_empty(), "{:?}", violations(&CLEAN));
+    }
+
+    #[test]
+    fn neighbours_eating_at_once_are_reported() {
+        // [1] eats without waiting for the fork.
+        let found = altered(10, r#"{"rank":1,"time_us":25,"lamport":9,"kind":"eat_start"}"#);
+        assert!(reports(&found, "[0] ate at 20..30us while its neighbour [1] ate at 25..40us"), "{:?}", found);
+    }
+
+    #[test]
+    fn giving_away_a_fork_that_is_not_held_is_reported() {
+        let mut lines = CLEAN.to_vec();
+        lines.push(r#"{"rank":1,"time_us":50,"lamport":11,"kind":"fork_given","fork":0,"peer":0,"before":"dirty","after":"missing"}"#);
+        lines.push(r#"{"rank":1,"time_us":51,"lamport":12,"kind":"fork_given","fork":0,"peer":0,"before":"missing","after":"missing"}"#);
+        let found = violations(&lines);
+        assert!(reports(&found, "[1] gave away the fork it shares with [0] at 51us without holding it"), "{:?}", found);
+    }
+
+    #[test]
+    fn a_fork_received_before_it_was_given_is_reported() {
+        let found = altered(9, r#"{"rank":1,"time_us":32,"lamport":6,"kind":"fork_received","fork":0,"peer":0,"before":"missing","after":"clean"}"#);
+        assert!(reports(&found, "fork 0 reached [1] at 32us before [0] gave it away at 31us"), "{:?}", found);
+    }
+}
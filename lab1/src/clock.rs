@@ -0,0 +1,47 @@
+/// Logical time carried by every message. The vector part is only present when the run was
+/// started with `--clock vector`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    pub lamport: u64,
+    pub vector: Option<Vec<u64>>,
+}
+
+/// A Lamport clock with an optional vector clock next to it, advanced on every send and receive.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    rank: usize,
+    lamport: u64,
+    vector: Option<Vec<u64>>,
+}
+
+impl Clock {
+    pub fn new(size: i32, rank: i32, vector: bool) -> Self {
+        Self {
+            rank: rank as usize,
+            lamport: 0,
+            vector: vector.then(|| vec![0; size as usize]),
+        }
+    }
+
+    pub fn stamp(&self) -> Stamp {
+        Stamp { lamport: self.lamport, vector: self.vector.clone() }
+    }
+
+    pub fn on_send(&mut self) -> Stamp {
+        self.lamport += 1;
+        if let Some(vector) = &mut self.vector {
+            vector[self.rank] += 1;
+        }
+        self.stamp()
+    }
+
+    pub fn on_receive(&mut self, stamp: &Stamp) {
+        self.lamport = self.lamport.max(stamp.lamport) + 1;
+        if let (Some(vector), Some(other)) = (&mut self.vector, &stamp.vector) {
+            for (mine, theirs) in vector.iter_mut().zip(other) {
+                *mine = (*mine).max(*theirs);
+            }
+            vector[self.rank] += 1;
+        }
+    }
+}
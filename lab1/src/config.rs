@@ -0,0 +1,358 @@
+use crate::{ fault::{ self, Faults }, model_check::{ MAX_PHILOSOPHERS, MIN_PHILOSOPHERS }, sim::Schedule, strategy::{ Acquisition, StrategyKind }, topology::{ Graph, Topology } };
+use rand::Rng;
+use std::{ collections::HashMap, fs, str::FromStr, time::Duration };
+
+const USAGE: &str = "usage: lab1 [--config FILE] [--meals N] [--duration SECS] [--seed N]
+            [--log console|jsonl|tui] [--log-file PATH] [--clock lamport|vector]
+            [--stats-format csv|json] [--stats-file PATH]
+            [--strategy chandy-misra|resource-order|waiter|token-ring|drinking]
+            [--acquire concurrent|sequential] [--faults FAULTS] [--fault-on MESSAGES]
+            [--topology GRAPH] [--priority LIST] [--threads N]
+            [--simulate random|round-robin|adversarial] [--ranks N] [--model-check N]
+            [--think DIST] [--eat DIST] [--think.RANK DIST] [--eat.RANK DIST]
+
+  TIME is a number with an optional unit: us, ms or s (default s), e.g. 250us, 1.5ms, 2
+  DIST is one of: fixed:TIME, uniform:TIME..TIME, exp:MEAN
+  PATH may contain {rank}, which is replaced by the rank writing to it (default: stdout)
+  tui streams every rank's events to rank 0, which draws a live dashboard of the whole table instead
+  of a log; it works with --threads and under MPI
+  rank 0 prints a table of every rank's statistics at the end, and --stats-file also writes them
+  to a file as csv (default) or json
+  --acquire decides whether Chandy-Misra asks for all its missing forks at once (default) or for one
+  after the other, each time waiting for the fork before asking for the next
+  drinking puts a bottle on every fork's edge and lets each session need a random subset of the
+  bottles, settling conflicts with Chandy-Misra forks; --meals then counts drinking sessions
+  FAULTS is a list of faults that hit the messages a rank sends, separated by commas: drop:P,
+  duplicate:P (a copy arrives again after the next message to the same rank), reorder:P (the message
+  arrives after the next one to the same rank) and delay:P:DIST (later messages to the same rank wait
+  behind it), each with probability P. --fault-on limits them to some messages, e.g. give,request.
+  The faults replay with --seed, and --simulate takes all but delay, as it decides when messages arrive
+  GRAPH says who shares a fork with whom: ring (default), grid:ROWSxCOLS, complete, star (around
+  rank 0) or edges:FILE with one pair of ranks per line
+  LIST holds one number per rank, separated by commas. Every fork starts out dirty with the end of
+  lower priority, which has to give it up, so higher priorities eat first (default: the rank)
+  --threads N runs N philosophers as threads of a single process, without MPI
+  --simulate runs --ranks philosophers (default 5) on virtual time in a single thread, delivering
+  messages in the given order; it needs --meals or --duration and replays exactly with --seed
+  --model-check N explores every interleaving of N philosophers (2 to 5) using --strategy and
+  --topology, and reports the shortest run to two neighbours eating together, to a deadlock, or to
+  a philosopher that stays hungry forever although every step that keeps being possible is taken
+
+  FILE holds one `key = value` pair per line, using the option names without the dashes.
+  Options given on the command line override the ones read from FILE.";
+
+/// How long a single think or eat phase lasts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Timing {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    Exponential(Duration),
+}
+
+impl Timing {
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Duration {
+        match self {
+            Timing::Fixed(duration) => *duration,
+            Timing::Uniform(min, max) => rng.random_range(*min..=*max),
+            Timing::Exponential(mean) => {
+                let u: f64 = rng.random();
+                mean.mul_f64(-(1.0 - u).ln())
+            }
+        }
+    }
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid timing => {}", s);
+        let (kind, args) = s.split_once(':').ok_or_else(invalid)?;
+
+        match kind {
+            "fixed" => Ok(Timing::Fixed(parse_duration(args)?)),
+            "uniform" => {
+                let (min, max) = args.split_once("..").ok_or_else(invalid)?;
+                let (min, max) = (parse_duration(min)?, parse_duration(max)?);
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(Timing::Uniform(min, max))
+            }
+            "exp" => Ok(Timing::Exponential(parse_duration(args)?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, scale) = if let Some(number) = s.strip_suffix("us") {
+        (number, 1e-6)
+    } else if let Some(number) = s.strip_suffix("ms") {
+        (number, 1e-3)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1.0)
+    } else {
+        (s, 1.0)
+    };
+
+    number.parse::<f64>().ok()
+        .and_then(|value| Duration::try_from_secs_f64(value * scale).ok())
+        .ok_or(format!("invalid duration => {}", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Console,
+    JsonLines,
+    Tui,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "console" => Ok(LogFormat::Console),
+            "jsonl" => Ok(LogFormat::JsonLines),
+            "tui" => Ok(LogFormat::Tui),
+            _ => Err(format!("invalid log format => {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(StatsFormat::Csv),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(format!("invalid statistics format => {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub meals: Option<u32>,
+    pub duration: Option<Duration>,
+    pub seed: Option<u64>,
+    pub think: Timing,
+    pub eat: Timing,
+    pub think_overrides: HashMap<i32, Timing>,
+    pub eat_overrides: HashMap<i32, Timing>,
+    pub log: LogFormat,
+    pub log_file: Option<String>,
+    pub stats_format: StatsFormat,
+    pub stats_file: Option<String>,
+    pub vector_clock: bool,
+    pub strategy: StrategyKind,
+    pub acquisition: Acquisition,
+    pub faults: Faults,
+    pub topology: Topology,
+    pub priority: Option<Vec<i64>>,
+    pub threads: Option<i32>,
+    pub simulate: Option<Schedule>,
+    pub ranks: i32,
+    pub model_check: Option<i32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            meals: None,
+            duration: None,
+            seed: None,
+            think: Timing::Uniform(Duration::from_secs(2), Duration::from_secs(5)),
+            eat: Timing::Fixed(Duration::from_secs(2)),
+            think_overrides: HashMap::new(),
+            eat_overrides: HashMap::new(),
+            log: LogFormat::Console,
+            log_file: None,
+            stats_format: StatsFormat::Csv,
+            stats_file: None,
+            vector_clock: false,
+            strategy: StrategyKind::ChandyMisra,
+            acquisition: Acquisition::Concurrent,
+            faults: Faults::default(),
+            topology: Topology::Ring,
+            priority: None,
+            threads: None,
+            simulate: None,
+            ranks: 5,
+            model_check: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self, String> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Applies `--config FILE` first and the other options after it, so the command line wins, and
+    /// only then checks how the options go together.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut settings = Vec::new();
+        let mut config_file = None;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let key = arg.strip_prefix("--").ok_or(format!("unknown argument => {}\n{}", arg, USAGE))?;
+            let value = args.next().ok_or(format!("missing value for {}\n{}", arg, USAGE))?;
+            if key == "config" {
+                config_file = Some(value);
+            } else {
+                settings.push((key.to_string(), value));
+            }
+        }
+
+        let mut config = Config::default();
+        if let Some(path) = config_file {
+            config.load_file(&path)?;
+        }
+        for (key, value) in settings {
+            config.set(&key, &value).map_err(|err| format!("{}\n{}", err, USAGE))?;
+        }
+        if config.model_check.is_some_and(|size| !(MIN_PHILOSOPHERS..=MAX_PHILOSOPHERS).contains(&size)) {
+            return Err(format!("model checking handles {} to {} philosophers\n{}", MIN_PHILOSOPHERS, MAX_PHILOSOPHERS, USAGE));
+        }
+        if config.simulate.is_some() && config.meals.is_none() && config.duration.is_none() {
+            return Err(format!("a simulation needs --meals or --duration to end\n{}", USAGE));
+        }
+        if config.log == LogFormat::Tui && (config.simulate.is_some() || config.model_check.is_some()) {
+            return Err("--log tui needs a live run, with --threads or under MPI".to_string());
+        }
+        if config.faults.delays() && config.simulate.is_some() {
+            return Err("--simulate decides when messages arrive, so it takes no delay faults".to_string());
+        }
+        if !config.faults.is_empty() && config.model_check.is_some() {
+            return Err("--model-check explores every order of the messages, but not faults".to_string());
+        }
+
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(path).map_err(|err| format!("cannot read {} => {}", path, err))?;
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(format!("{}:{}: expected `key = value`", path, number + 1))?;
+            self.set(key.trim(), value.trim()).map_err(|err| format!("{}:{}: {}", path, number + 1, err))?;
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key.split_once('.') {
+            Some((phase @ ("think" | "eat"), rank)) => {
+                let rank = rank.parse().map_err(|_| format!("invalid rank => {}", key))?;
+                let overrides = if phase == "think" { &mut self.think_overrides } else { &mut self.eat_overrides };
+                overrides.insert(rank, value.parse()?);
+            }
+            Some(_) => return Err(format!("unknown option => {}", key)),
+            None => match key {
+                "meals" => self.meals = Some(value.parse().map_err(|_| format!("invalid meal count => {}", value))?),
+                "duration" => self.duration = Some(parse_duration(value)?),
+                "seed" => self.seed = Some(value.parse().map_err(|_| format!("invalid seed => {}", value))?),
+                "think" => self.think = value.parse()?,
+                "eat" => self.eat = value.parse()?,
+                "log" => self.log = value.parse()?,
+                "log-file" => self.log_file = Some(value.to_string()),
+                "stats-format" => self.stats_format = value.parse()?,
+                "stats-file" => self.stats_file = Some(value.to_string()),
+                "clock" => self.vector_clock = match value {
+                    "lamport" => false,
+                    "vector" => true,
+                    _ => return Err(format!("invalid clock => {}", value)),
+                },
+                "strategy" => self.strategy = value.parse()?,
+                "acquire" => self.acquisition = value.parse()?,
+                "faults" => self.faults.odds = value.parse::<Faults>()?.odds,
+                "fault-on" => self.faults.messages = fault::parse_messages(value)?,
+                "topology" => self.topology = value.parse()?,
+                "priority" => self.priority = Some(
+                    value.split(',').map(|priority| priority.trim().parse()).collect::<Result<_, _>>().map_err(|_| format!("invalid priorities => {}", value))?
+                ),
+                "simulate" => self.simulate = Some(value.parse()?),
+                "ranks" => self.ranks = value.parse().map_err(|_| format!("invalid rank count => {}", value))?,
+                "model-check" => self.model_check = Some(value.parse().map_err(|_| format!("invalid rank count => {}", value))?),
+                "threads" => self.threads = Some(value.parse().map_err(|_| format!("invalid thread count => {}", value))?),
+                _ => return Err(format!("unknown option => {}", key)),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// The conflict graph of `size` philosophers, with the priorities if any were given.
+    pub fn graph(&self, size: i32) -> Result<Graph, String> {
+        let graph = self.topology.graph(size)?;
+        match &self.priority {
+            Some(priority) => graph.with_priority(priority.clone()),
+            None => Ok(graph),
+        }
+    }
+
+    pub fn think_time(&self, rank: i32) -> &Timing {
+        self.think_overrides.get(&rank).unwrap_or(&self.think)
+    }
+
+    pub fn eat_time(&self, rank: i32) -> &Timing {
+        self.eat_overrides.get(&rank).unwrap_or(&self.eat)
+    }
+
+    pub fn log_file(&self, rank: i32) -> Option<String> {
+        self.log_file.as_ref().map(|path| path.replace("{rank}", &rank.to_string()))
+    }
+
+    /// A run without `--meals` or `--duration` never stops, like the original endless loop.
+    pub fn should_stop(&self, meals_eaten: u32, elapsed: Duration) -> bool {
+        self.meals.is_some_and(|meals| meals_eaten >= meals)
+            || self.duration.is_some_and(|duration| elapsed >= duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Parses `args` after `--config` with a file holding `lines`.
+    fn parse_with_file(name: &str, lines: &str, args: &[&str]) -> Result<Config, String> {
+        let path = std::env::temp_dir().join(format!("lab1-{}-{}.conf", name, process::id()));
+        fs::write(&path, lines).unwrap();
+        let all = ["--config", path.to_str().unwrap()].into_iter().chain(args.iter().copied()).map(String::from);
+        let config = Config::parse(all.collect::<Vec<_>>());
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_file_before_options_are_checked_together() {
+        // Delays from the file would clash with --simulate, but --faults replaces them.
+        let config = parse_with_file("faults", "faults = delay:0.5:fixed:1ms\nmeals = 1\n", &["--simulate", "random", "--faults", "drop:0.1"]).unwrap();
+        assert!(!config.faults.delays() && !config.faults.is_empty());
+        let config = parse_with_file("tui", "log = tui\n", &["--simulate", "random", "--log", "jsonl", "--meals", "1"]).unwrap();
+        assert_eq!(config.log, LogFormat::JsonLines);
+
+        // What is left after every option is applied still has to go together.
+        let clash = parse_with_file("clash", "simulate = random\nmeals = 1\n", &["--faults", "delay:0.5:fixed:1ms"]).unwrap_err();
+        assert!(clash.contains("no delay faults"), "{}", clash);
+        let clash = parse_with_file("model-check", "faults = drop:0.1\n", &["--model-check", "3"]).unwrap_err();
+        assert!(clash.contains("not faults"), "{}", clash);
+    }
+}
@@ -0,0 +1,463 @@
+use crate::{ event::{ Event, EventKind, EventSink }, topology::Graph, ForkId, ForkState, Phase };
+#[cfg(feature = "mpi")]
+use mpi::{ topology::SimpleCommunicator, traits::* };
+use std::{ collections::{ BTreeSet, VecDeque }, fmt::{ self, Write as _ }, io::{ self, Write }, sync::mpsc::{ Receiver, RecvTimeoutError }, time::{ Duration, Instant } };
+#[cfg(feature = "mpi")]
+use std::{ sync::mpsc::{ self, Sender }, thread::{ self, JoinHandle } };
+
+/// The screen is redrawn at most this often.
+const REFRESH: Duration = Duration::from_millis(100);
+/// How many of the latest events the dashboard shows.
+const RECENT: usize = 12;
+
+/// Every kind, in the order of its code in an update.
+const KINDS: [EventKind; 15] = [
+    EventKind::ForkInit, EventKind::ThinkStart, EventKind::ThinkEnd, EventKind::RequestSent, EventKind::RequestReceived,
+    EventKind::ForkReceived, EventKind::ForkGiven, EventKind::BottleRequested, EventKind::BottleReceived, EventKind::BottleGiven,
+    EventKind::EatStart, EventKind::EatEnd, EventKind::PeerDone, EventKind::Done, EventKind::Leave,
+];
+
+/// rank (4), time_us (8), lamport (8), kind (1), fork (4), peer (4), before (1), after (1)
+const UPDATE_LEN: usize = 31;
+
+fn state_code(state: Option<ForkState>) -> u8 {
+    match state {
+        None => 0,
+        Some(ForkState::MISSING) => 1,
+        Some(ForkState::CLEAN) => 2,
+        Some(ForkState::DIRTY) => 3,
+    }
+}
+
+fn state_from(code: u8) -> Option<Option<ForkState>> {
+    match code {
+        0 => Some(None),
+        1 => Some(Some(ForkState::MISSING)),
+        2 => Some(Some(ForkState::CLEAN)),
+        3 => Some(Some(ForkState::DIRTY)),
+        _ => None,
+    }
+}
+
+/// Packs what the dashboard needs of an event into a fixed `UPDATE_LEN` bytes, little endian.
+/// A missing fork or peer is sent as all ones, and the vector clock and wait are left out.
+pub fn encode(event: &Event) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(UPDATE_LEN);
+    buf.extend_from_slice(&event.rank.to_le_bytes());
+    buf.extend_from_slice(&(event.time_us as u64).to_le_bytes());
+    buf.extend_from_slice(&event.lamport.to_le_bytes());
+    buf.push(KINDS.iter().position(|&kind| kind == event.kind).unwrap() as u8);
+    buf.extend_from_slice(&event.fork.unwrap_or(ForkId::MAX).to_le_bytes());
+    buf.extend_from_slice(&event.peer.unwrap_or(-1).to_le_bytes());
+    buf.push(state_code(event.before));
+    buf.push(state_code(event.after));
+    buf
+}
+
+/// The inverse of `encode`, or `None` for anything `encode` cannot have written.
+pub fn decode(buf: &[u8]) -> Option<Event> {
+    if buf.len() != UPDATE_LEN {
+        return None;
+    }
+    let bytes = |at: usize| -> [u8; 4] { buf[at..at + 4].try_into().unwrap() };
+    let long = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+    let fork = u32::from_le_bytes(bytes(21));
+    let peer = i32::from_le_bytes(bytes(25));
+
+    Some(Event {
+        rank: i32::from_le_bytes(bytes(0)),
+        time_us: long(4).into(),
+        lamport: long(12),
+        vector: None,
+        kind: *KINDS.get(buf[20] as usize)?,
+        fork: (fork != ForkId::MAX).then_some(fork),
+        peer: (peer != -1).then_some(peer),
+        before: state_from(buf[29])?,
+        after: state_from(buf[30])?,
+        waited_us: None,
+    })
+}
+
+/// Where a fork is, as far as the updates tell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ForkView {
+    holder: i32,
+    state: ForkState,
+    /// Given away by the other end, but not received by `holder` yet.
+    in_flight: bool,
+    /// Of the event this view comes from. Updates from different ranks can arrive out of order,
+    /// but receiving a fork always happens at a later Lamport time than giving it away.
+    lamport: u64,
+}
+
+/// A philosopher as far as the updates tell: no phase before its first one, and whether it is
+/// done or has left the table.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Seat {
+    phase: Option<Phase>,
+    done: bool,
+    left: bool,
+}
+
+impl fmt::Display for Seat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.phase {
+            _ if self.left => write!(f, "left"),
+            _ if self.done => write!(f, "done"),
+            Some(phase) => write!(f, "{}", phase),
+            None => write!(f, "seated"),
+        }
+    }
+}
+
+/// The whole table as rebuilt from the events of every rank.
+pub struct Dashboard {
+    graph: Graph,
+    seats: Vec<Seat>,
+    meals: Vec<u32>,
+    forks: Vec<ForkView>,
+    /// Per rank, the neighbours whose requests it has not answered yet.
+    pending: Vec<BTreeSet<i32>>,
+    forks_passed: u64,
+    requests: u64,
+    latest_us: u128,
+    recent: VecDeque<String>,
+}
+
+impl Dashboard {
+    /// Every fork starts out dirty with the end `graph` hands it to, until `fork_init` says otherwise.
+    pub fn new(graph: &Graph) -> Self {
+        let size = graph.size() as usize;
+        let forks = (0..graph.edges().len() as ForkId)
+            .map(|fork| ForkView { holder: graph.initial_holder(fork), state: ForkState::DIRTY, in_flight: false, lamport: 0 })
+            .collect();
+        Self {
+            graph: graph.clone(),
+            seats: vec![Seat::default(); size],
+            meals: vec![0; size],
+            forks,
+            pending: vec![BTreeSet::new(); size],
+            forks_passed: 0,
+            requests: 0,
+            latest_us: 0,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn all_left(&self) -> bool {
+        self.seats.iter().all(|seat| seat.left)
+    }
+
+    /// Ignores events about ranks or forks that are not on this table.
+    pub fn apply(&mut self, event: &Event) {
+        let rank = event.rank;
+        if !(0..self.graph.size()).contains(&rank) || event.fork.is_some_and(|fork| fork as usize >= self.forks.len()) {
+            return;
+        }
+        self.latest_us = self.latest_us.max(event.time_us);
+        let fork = event.fork.map(|fork| fork as usize).filter(|&fork| self.forks[fork].lamport <= event.lamport);
+        let lamport = event.lamport;
+
+        match (event.kind, fork, event.peer) {
+            (EventKind::ThinkStart, ..) => self.seats[rank as usize].phase = Some(Phase::Thinking),
+            (EventKind::EatEnd, ..) => {
+                self.seats[rank as usize].phase = Some(Phase::Thinking);
+                // Eating dirties every fork in hand.
+                for view in self.forks.iter_mut().filter(|view| view.holder == rank && !view.in_flight) {
+                    view.state = ForkState::DIRTY;
+                }
+            }
+            (EventKind::ThinkEnd, ..) => self.seats[rank as usize].phase = Some(Phase::Hungry),
+            (EventKind::EatStart, ..) => {
+                self.seats[rank as usize].phase = Some(Phase::Eating);
+                self.meals[rank as usize] += 1;
+            }
+            (EventKind::Done, ..) => self.seats[rank as usize].done = true,
+            (EventKind::Leave, ..) => self.seats[rank as usize].left = true,
+            (EventKind::ForkInit, Some(fork), _) if event.after != Some(ForkState::MISSING) => {
+                self.forks[fork] = ForkView { holder: rank, state: event.after.unwrap_or(ForkState::DIRTY), in_flight: false, lamport };
+            }
+            (EventKind::RequestReceived, _, Some(peer)) => {
+                self.pending[rank as usize].insert(peer);
+                self.requests += 1;
+            }
+            (EventKind::ForkGiven, fork, Some(peer)) => {
+                self.pending[rank as usize].remove(&peer);
+                if let Some(fork) = fork {
+                    self.forks[fork] = ForkView { holder: peer, state: ForkState::MISSING, in_flight: true, lamport };
+                }
+                self.forks_passed += 1;
+            }
+            (EventKind::ForkReceived, Some(fork), _) => {
+                self.forks[fork] = ForkView { holder: rank, state: ForkState::CLEAN, in_flight: false, lamport };
+            }
+            _ => {}
+        }
+
+        if let Some(line) = describe(event) {
+            if self.recent.len() == RECENT {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(format!("{:>9.3}s  {}", event.time_us as f64 / 1e6, line));
+        }
+    }
+
+    /// Totals on top, the philosophers and the forks side by side, and the latest events below.
+    pub fn render(&self) -> String {
+        let size = self.graph.size() as usize;
+        let left = self.seats.iter().filter(|seat| seat.left).count();
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{} philosophers   {:.1}s   meals {}   forks passed {}   requests {}   left {}/{}\n",
+            size, self.latest_us as f64 / 1e6, self.meals.iter().sum::<u32>(), self.forks_passed, self.requests, left, size
+        ).unwrap();
+
+        let philosophers: Vec<String> = (0..size)
+            .map(|rank| {
+                let pending: Vec<String> = self.pending[rank].iter().map(|peer| format!("[{}]", peer)).collect();
+                format!("{:>5}  {:<9} {:>6}  {:<18}", rank, self.seats[rank].to_string(), self.meals[rank], pending.join(" "))
+            })
+            .collect();
+        let forks: Vec<String> = self.graph.edges().iter().zip(&self.forks).enumerate()
+            .map(|(fork, (&(a, b), view))| {
+                let (holder, state) = if view.in_flight {
+                    (format!("->[{}]", view.holder), "")
+                } else {
+                    (format!("[{}]", view.holder), if view.state == ForkState::CLEAN { "clean" } else { "dirty" })
+                };
+                format!("{:>5}  {:<9} {:<7} {}", fork, format!("[{}]-[{}]", a, b), holder, state)
+            })
+            .collect();
+
+        let header = format!("{:>5}  {:<9} {:>6}  {:<18}", "rank", "state", "meals", "waiting for it");
+        writeln!(out, "{:<44}  {:>5}  {:<9} {:<7} state", header, "fork", "ends", "holder").unwrap();
+        for row in 0..philosophers.len().max(forks.len()) {
+            let philosopher = philosophers.get(row).map_or("", String::as_str);
+            let fork = forks.get(row).map_or("", String::as_str);
+            writeln!(out, "{:<44}  {}", philosopher, fork).unwrap();
+        }
+
+        writeln!(out, "\nrecent events").unwrap();
+        for line in &self.recent {
+            writeln!(out, "{}", line).unwrap();
+        }
+        out
+    }
+}
+
+fn describe(event: &Event) -> Option<String> {
+    let (rank, fork, peer) = (event.rank, event.fork.unwrap_or_default(), event.peer.unwrap_or(-1));
+    Some(match event.kind {
+        EventKind::ForkInit | EventKind::PeerDone => return None,
+        EventKind::ThinkStart => format!("[{}] thinks", rank),
+        EventKind::ThinkEnd => format!("[{}] is hungry", rank),
+        EventKind::RequestSent => format!("[{}] asks [{}] for fork {}", rank, peer, fork),
+        EventKind::RequestReceived => format!("[{}] is asked for fork {} by [{}]", rank, fork, peer),
+        EventKind::ForkReceived => format!("[{}] gets fork {} from [{}]", rank, fork, peer),
+        EventKind::ForkGiven => format!("[{}] gives fork {} to [{}]", rank, fork, peer),
+        EventKind::BottleRequested => format!("[{}] asks [{}] for bottle {}", rank, peer, fork),
+        EventKind::BottleReceived => format!("[{}] gets bottle {} from [{}]", rank, fork, peer),
+        EventKind::BottleGiven => format!("[{}] gives bottle {} to [{}]", rank, fork, peer),
+        EventKind::EatStart => format!("[{}] eats", rank),
+        EventKind::EatEnd => format!("[{}] finished eating", rank),
+        EventKind::Done => format!("[{}] is done", rank),
+        EventKind::Leave => format!("[{}] leaves the table", rank),
+    })
+}
+
+/// Redraws the whole terminal, at most every `REFRESH` unless forced.
+#[derive(Default)]
+struct Screen {
+    drawn: Option<Instant>,
+}
+
+impl Screen {
+    fn draw(&mut self, dashboard: &Dashboard, force: bool) {
+        if !force && self.drawn.is_some_and(|drawn| drawn.elapsed() < REFRESH) {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+        let mut out = io::stdout().lock();
+        // Home the cursor and clear the screen, so every frame starts at the top.
+        let _ = write!(out, "\x1b[H\x1b[2J{}", dashboard.render()).and_then(|_| out.flush());
+    }
+}
+
+/// Hands every event to `forward` as an update, for a dashboard that is drawn somewhere else.
+pub struct ForwardSink<F: FnMut(Vec<u8>)> {
+    forward: F,
+}
+
+impl<F: FnMut(Vec<u8>)> ForwardSink<F> {
+    pub fn new(forward: F) -> Self {
+        Self { forward }
+    }
+}
+
+impl<F: FnMut(Vec<u8>)> EventSink for ForwardSink<F> {
+    fn record(&mut self, event: &Event) {
+        (self.forward)(encode(event));
+    }
+}
+
+/// Draws `dashboard` from `updates` until every philosopher has left, or all senders hung up.
+pub fn watch(mut dashboard: Dashboard, updates: Receiver<Vec<u8>>) {
+    let mut screen = Screen::default();
+    while !dashboard.all_left() {
+        match updates.recv_timeout(REFRESH) {
+            Ok(update) => {
+                if let Some(event) = decode(&update) {
+                    dashboard.apply(&event);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        screen.draw(&dashboard, false);
+    }
+    screen.draw(&dashboard, true);
+}
+
+/// Tag of the updates the ranks send to rank 0, so they never mix with the protocol's messages.
+#[cfg(feature = "mpi")]
+pub const UPDATES: mpi::Tag = 1;
+
+/// Rank 0 under MPI: `watch` runs on a thread of its own, fed with rank 0's own events and with the
+/// updates another thread receives from everyone else, so the screen keeps moving while rank 0
+/// waits.
+#[cfg(feature = "mpi")]
+struct MpiDashboard {
+    world: SimpleCommunicator,
+    updates: Sender<Vec<u8>>,
+    watcher: Option<JoinHandle<()>>,
+    receiver: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "mpi")]
+impl EventSink for MpiDashboard {
+    fn record(&mut self, event: &Event) {
+        let _ = self.updates.send(encode(event));
+    }
+
+    /// Keeps drawing until everyone else has left too.
+    fn close(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+        // Nobody sends updates after leaving, so the receiving thread can stop. An update is never
+        // empty, so an empty one tells it to.
+        if let Some(receiver) = self.receiver.take() {
+            self.world.this_process().send_with_tag(&[0u8; 0][..], UPDATES);
+            let _ = receiver.join();
+        }
+    }
+}
+
+/// The sink of `--log tui` under MPI: the dashboard on rank 0, and everyone else sending their
+/// updates to it. MPI has to be initialised with `Threading::Multiple`.
+#[cfg(feature = "mpi")]
+pub fn mpi_sink(world: &SimpleCommunicator, graph: &Graph) -> Box<dyn EventSink> {
+    // The sink and the threads need a world of their own, where `world` is only borrowed.
+    if world.rank() != 0 {
+        let world = SimpleCommunicator::world();
+        return Box::new(ForwardSink::new(move |update| world.process_at_rank(0).send_with_tag(&update[..], UPDATES)));
+    }
+
+    let (updates, monitor) = mpsc::channel();
+    let dashboard = Dashboard::new(graph);
+    let watcher = thread::spawn(move || watch(dashboard, monitor));
+    let forward = updates.clone();
+    let receiver = thread::spawn(move || {
+        let world = SimpleCommunicator::world();
+        loop {
+            let (update, _) = world.any_process().receive_vec_with_tag::<u8>(UPDATES);
+            if update.is_empty() || forward.send(update).is_err() {
+                break;
+            }
+        }
+    });
+    Box::new(MpiDashboard { world: SimpleCommunicator::world(), updates, watcher: Some(watcher), receiver: Some(receiver) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topology::Topology;
+
+    fn event(rank: i32, time_us: u128, kind: EventKind, fork: Option<ForkId>, peer: Option<i32>, after: Option<ForkState>) -> Event {
+        Event { rank, time_us, lamport: time_us as u64, vector: None, kind, fork, peer, before: None, after, waited_us: None }
+    }
+
+    #[test]
+    fn updates_survive_the_round_trip() {
+        for (index, &kind) in KINDS.iter().enumerate() {
+            let sent = event(index as i32, 1 << 40, kind, (index % 2 == 0).then_some(7), (index % 3 == 0).then_some(2), Some(ForkState::CLEAN));
+            let update = encode(&sent);
+            assert_eq!(update.len(), UPDATE_LEN);
+
+            let received = decode(&update).unwrap();
+            assert_eq!(received.kind.name(), kind.name());
+            assert_eq!((received.rank, received.time_us, received.lamport), (sent.rank, sent.time_us, sent.lamport));
+            assert_eq!((received.fork, received.peer, received.before, received.after), (sent.fork, sent.peer, sent.before, sent.after));
+        }
+        assert!(decode(&[0; UPDATE_LEN - 1]).is_none());
+        let mut unknown = encode(&event(0, 0, EventKind::Leave, None, None, None));
+        unknown[20] = KINDS.len() as u8;
+        assert!(decode(&unknown).is_none());
+    }
+
+    #[test]
+    fn the_dashboard_follows_forks_requests_and_meals() {
+        // Fork 0 joins [0] and [1], fork 1 joins [0] and [2], fork 2 joins [1] and [2].
+        let mut dashboard = Dashboard::new(&Topology::Ring.graph(3).unwrap());
+        let events = [
+            event(1, 10, EventKind::ThinkEnd, None, None, None),
+            event(1, 11, EventKind::RequestSent, Some(0), Some(0), Some(ForkState::MISSING)),
+            event(0, 12, EventKind::RequestReceived, Some(0), Some(1), Some(ForkState::DIRTY)),
+        ];
+        for event in &events {
+            dashboard.apply(event);
+        }
+        assert_eq!(dashboard.seats[1].phase, Some(Phase::Hungry));
+        assert_eq!(dashboard.pending[0], BTreeSet::from([1]));
+
+        dashboard.apply(&event(0, 13, EventKind::ForkGiven, Some(0), Some(1), Some(ForkState::MISSING)));
+        assert!(dashboard.pending[0].is_empty());
+        assert_eq!(dashboard.forks[0], ForkView { holder: 1, state: ForkState::MISSING, in_flight: true, lamport: 13 });
+        assert!(dashboard.render().contains("->[1]"));
+
+        dashboard.apply(&event(1, 14, EventKind::ForkReceived, Some(0), Some(0), Some(ForkState::CLEAN)));
+        dashboard.apply(&event(1, 15, EventKind::EatStart, None, None, None));
+        assert_eq!(dashboard.forks[0], ForkView { holder: 1, state: ForkState::CLEAN, in_flight: false, lamport: 14 });
+
+        // [2] got fork 2 from [1], and the update of [1] giving it away only comes after.
+        dashboard.apply(&event(2, 17, EventKind::ForkReceived, Some(2), Some(1), Some(ForkState::CLEAN)));
+        dashboard.apply(&event(1, 16, EventKind::ForkGiven, Some(2), Some(2), Some(ForkState::MISSING)));
+        assert_eq!(dashboard.forks[2], ForkView { holder: 2, state: ForkState::CLEAN, in_flight: false, lamport: 17 });
+        assert_eq!((dashboard.seats[1].phase, dashboard.meals[1]), (Some(Phase::Eating), 1));
+
+        // With fork 2 back, [1] holds forks 0 and 2 clean, and dirties both by eating with them.
+        dashboard.apply(&event(1, 18, EventKind::ForkReceived, Some(2), Some(2), Some(ForkState::CLEAN)));
+        dashboard.apply(&event(1, 19, EventKind::EatEnd, None, None, None));
+        assert_eq!(dashboard.seats[1].phase, Some(Phase::Thinking));
+        assert_eq!(dashboard.forks[0], ForkView { holder: 1, state: ForkState::DIRTY, in_flight: false, lamport: 14 });
+        assert_eq!(dashboard.forks[2], ForkView { holder: 1, state: ForkState::DIRTY, in_flight: false, lamport: 18 });
+        assert!(dashboard.render().contains("    2  [1]-[2]   [1]     dirty"));
+
+        // Strangers and unknown forks are ignored rather than trusted.
+        dashboard.apply(&event(7, 16, EventKind::EatStart, None, None, None));
+        dashboard.apply(&event(0, 16, EventKind::ForkReceived, Some(9), Some(1), Some(ForkState::CLEAN)));
+        assert_eq!(dashboard.meals.iter().sum::<u32>(), 1);
+
+        let screen = dashboard.render();
+        assert!(screen.starts_with("3 philosophers"));
+        assert!(screen.contains("[1] eats"));
+        dashboard.apply(&event(0, 20, EventKind::Done, None, None, None));
+        assert!(dashboard.render().contains("    0  done"));
+        for rank in 0..3 {
+            dashboard.apply(&event(rank, 20, EventKind::Leave, None, None, None));
+        }
+        assert!(dashboard.all_left());
+    }
+}
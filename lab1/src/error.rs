@@ -0,0 +1,67 @@
+use crate::{ wire::WireError, ForkId, Message, Phase };
+use std::{ fmt, io };
+
+#[derive(Debug)]
+pub enum PhilosopherError {
+    TooFewPhilosophers(i32),
+    Topology(String),
+    ForkOwners { fork: ForkId, owners: Vec<i32> },
+    PrecedenceCycle(Vec<i32>),
+    UnexpectedMessage { context: &'static str, message: Message, sender: i32 },
+    NoForkMissing,
+    IllegalTransition { from: Phase, to: Phase },
+    EatingWithoutForks { missing: Vec<i32> },
+    UnknownFork { fork: ForkId, sender: i32 },
+    MalformedMessage { sender: i32, err: WireError },
+    EventLog { path: String, err: io::Error },
+    Stats { path: String, err: io::Error },
+}
+
+impl PhilosopherError {
+    /// The code handed to `MPI_Abort`, and with it the exit status `mpirun` reports.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PhilosopherError::TooFewPhilosophers(_)
+            | PhilosopherError::Topology(_)
+            | PhilosopherError::ForkOwners { .. }
+            | PhilosopherError::PrecedenceCycle(_) => 3,
+            PhilosopherError::UnexpectedMessage { .. }
+            | PhilosopherError::NoForkMissing
+            | PhilosopherError::IllegalTransition { .. }
+            | PhilosopherError::EatingWithoutForks { .. }
+            | PhilosopherError::UnknownFork { .. } => 4,
+            PhilosopherError::MalformedMessage { .. } => 5,
+            PhilosopherError::EventLog { .. } | PhilosopherError::Stats { .. } => 6,
+        }
+    }
+}
+
+impl fmt::Display for PhilosopherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhilosopherError::TooFewPhilosophers(size) => write!(f, "the table needs at least 2 philosophers, but only {} rank(s) were started", size),
+            PhilosopherError::Topology(err) => write!(f, "cannot seat the philosophers => {}", err),
+            PhilosopherError::ForkOwners { fork, owners } if owners.is_empty() => write!(f, "fork {} would start out owned by neither of its ends", fork),
+            PhilosopherError::ForkOwners { fork, owners } => write!(f, "fork {} would start out owned by {:?} instead of exactly one of its ends", fork, owners),
+            PhilosopherError::PrecedenceCycle(ranks) => write!(f, "the initial forks make {:?} wait for each other in a cycle", ranks),
+            PhilosopherError::UnexpectedMessage { context, message, sender } => write!(f, "unexpected {:?} from [{}] while {}", message, sender, context),
+            PhilosopherError::NoForkMissing => write!(f, "asked to request a fork while holding all of them"),
+            PhilosopherError::IllegalTransition { from, to } => write!(f, "cannot go from {} to {}", from, to),
+            PhilosopherError::EatingWithoutForks { missing } => write!(f, "cannot eat without the forks shared with {:?}", missing),
+            PhilosopherError::UnknownFork { fork, sender } => write!(f, "[{}] referred to fork {}, which we do not share with it", sender, fork),
+            PhilosopherError::MalformedMessage { sender, err } => write!(f, "malformed message from [{}] => {}", sender, err),
+            PhilosopherError::EventLog { path, err } => write!(f, "cannot create event log {} => {}", path, err),
+            PhilosopherError::Stats { path, err } => write!(f, "cannot write statistics to {} => {}", path, err),
+        }
+    }
+}
+
+impl std::error::Error for PhilosopherError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PhilosopherError::MalformedMessage { err, .. } => Some(err),
+            PhilosopherError::EventLog { err, .. } | PhilosopherError::Stats { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
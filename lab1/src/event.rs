@@ -0,0 +1,271 @@
+use crate::{ clock::Clock, ForkId, ForkState };
+use std::{ cell::Cell, collections::BTreeMap, fs::File, io::{ self, BufWriter, Write }, rc::Rc, str::FromStr, time::{ Duration, Instant } };
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EventKind {
+    ForkInit,
+    ThinkStart,
+    ThinkEnd,
+    RequestSent,
+    RequestReceived,
+    ForkReceived,
+    ForkGiven,
+    BottleRequested,
+    BottleReceived,
+    BottleGiven,
+    EatStart,
+    EatEnd,
+    PeerDone,
+    Done,
+    Leave,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::ForkInit => "fork_init",
+            EventKind::ThinkStart => "think_start",
+            EventKind::ThinkEnd => "think_end",
+            EventKind::RequestSent => "request_sent",
+            EventKind::RequestReceived => "request_received",
+            EventKind::ForkReceived => "fork_received",
+            EventKind::ForkGiven => "fork_given",
+            EventKind::BottleRequested => "bottle_requested",
+            EventKind::BottleReceived => "bottle_received",
+            EventKind::BottleGiven => "bottle_given",
+            EventKind::EatStart => "eat_start",
+            EventKind::EatEnd => "eat_end",
+            EventKind::PeerDone => "peer_done",
+            EventKind::Done => "done",
+            EventKind::Leave => "leave",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    /// The inverse of `name`, for tools that read the event logs back.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fork_init" => Ok(EventKind::ForkInit),
+            "think_start" => Ok(EventKind::ThinkStart),
+            "think_end" => Ok(EventKind::ThinkEnd),
+            "request_sent" => Ok(EventKind::RequestSent),
+            "request_received" => Ok(EventKind::RequestReceived),
+            "fork_received" => Ok(EventKind::ForkReceived),
+            "fork_given" => Ok(EventKind::ForkGiven),
+            "bottle_requested" => Ok(EventKind::BottleRequested),
+            "bottle_received" => Ok(EventKind::BottleReceived),
+            "bottle_given" => Ok(EventKind::BottleGiven),
+            "eat_start" => Ok(EventKind::EatStart),
+            "eat_end" => Ok(EventKind::EatEnd),
+            "peer_done" => Ok(EventKind::PeerDone),
+            "done" => Ok(EventKind::Done),
+            "leave" => Ok(EventKind::Leave),
+            _ => Err(format!("invalid event kind => {}", s)),
+        }
+    }
+}
+
+/// A single state change of one philosopher. Fork events carry the fork, the neighbour it is
+/// shared with as `peer`, and its state before and after the change, as seen by `rank`. `lamport` and `vector` are the philosopher's
+/// logical clock right after the change, so events from different ranks can be merged causally.
+/// A fork given on request carries how long the request waited for it in `waited_us`. Bottle
+/// events of drinking philosophers carry the bottle in `fork`, since it has the same id.
+#[derive(Debug)]
+pub struct Event {
+    pub rank: i32,
+    pub time_us: u128,
+    pub lamport: u64,
+    pub vector: Option<Vec<u64>>,
+    pub kind: EventKind,
+    pub fork: Option<ForkId>,
+    pub peer: Option<i32>,
+    pub before: Option<ForkState>,
+    pub after: Option<ForkState>,
+    pub waited_us: Option<u128>,
+}
+
+pub trait EventSink {
+    fn record(&mut self, event: &Event);
+
+    /// Called once the philosopher has left the table.
+    fn close(&mut self) {}
+}
+
+/// Throws events away, for runs where only the outcome matters.
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    fn record(&mut self, _event: &Event) {}
+}
+
+/// The original indented stdout output, one column per rank.
+pub struct ConsoleSink {
+    indent: String,
+}
+
+impl ConsoleSink {
+    pub fn new(rank: i32) -> Self {
+        Self { indent: "      ".repeat(rank.try_into().unwrap()) }
+    }
+}
+
+impl EventSink for ConsoleSink {
+    fn record(&mut self, event: &Event) {
+        let (indent, rank) = (&self.indent, event.rank);
+        let fork = event.fork.map_or(String::new(), |fork| fork.to_string());
+        let peer = event.peer.unwrap_or(-1);
+        let clock = match &event.vector {
+            Some(vector) => format!("@{} {:?}", event.lamport, vector),
+            None => format!("@{}", event.lamport),
+        };
+
+        match event.kind {
+            EventKind::ForkInit => {}
+            EventKind::ThinkStart => println!("{}[{}] is thinking! {}", indent, rank, clock),
+            EventKind::ThinkEnd => println!("{}[{}] finished thinking! {}", indent, rank, clock),
+            EventKind::RequestSent => println!("{}[{}] requested fork {} from [{}]! {}", indent, rank, fork, peer, clock),
+            EventKind::RequestReceived => println!("{}[{}] got a request for fork {} from [{}]! {}", indent, rank, fork, peer, clock),
+            EventKind::ForkReceived => println!("{}[{}] received fork {} from [{}]! {}", indent, rank, fork, peer, clock),
+            EventKind::ForkGiven => match event.waited_us {
+                Some(waited) => println!("{}[{}] giving fork {} to [{}] after {}us! {}", indent, rank, fork, peer, waited, clock),
+                None => println!("{}[{}] giving fork {} to [{}]! {}", indent, rank, fork, peer, clock),
+            },
+            EventKind::BottleRequested => println!("{}[{}] requested bottle {} from [{}]! {}", indent, rank, fork, peer, clock),
+            EventKind::BottleReceived => println!("{}[{}] received bottle {} from [{}]! {}", indent, rank, fork, peer, clock),
+            EventKind::BottleGiven => println!("{}[{}] giving bottle {} to [{}]! {}", indent, rank, fork, peer, clock),
+            EventKind::EatStart => println!("{}Philosopher {} is eating! {}", indent, rank, clock),
+            EventKind::EatEnd => println!("{}[{}] finished eating! {}", indent, rank, clock),
+            EventKind::PeerDone => println!("{}[{}] learned that [{}] is done! {}", indent, rank, peer, clock),
+            EventKind::Done => println!("{}[{}] is done! {}", indent, rank, clock),
+            EventKind::Leave => println!("{}[{}] leaving the table! {}", indent, rank, clock),
+        }
+    }
+}
+
+/// One JSON object per line, flushed after every event so a killed run still leaves a usable log.
+pub struct JsonLinesSink<W: Write> {
+    out: W,
+}
+
+impl JsonLinesSink<BufWriter<File>> {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> EventSink for JsonLinesSink<W> {
+    fn record(&mut self, event: &Event) {
+        let fork = event.fork.map_or("null".to_string(), |fork| fork.to_string());
+        let peer = event.peer.map_or("null".to_string(), |peer| peer.to_string());
+        let waited = event.waited_us.map_or("null".to_string(), |waited| waited.to_string());
+        let fork_state = |state: &Option<ForkState>| match state {
+            Some(ForkState::MISSING) => "\"missing\"",
+            Some(ForkState::CLEAN) => "\"clean\"",
+            Some(ForkState::DIRTY) => "\"dirty\"",
+            None => "null",
+        };
+
+        let vector = event.vector.as_ref().map_or("null".to_string(), |vector| {
+            format!("[{}]", vector.iter().map(|time| time.to_string()).collect::<Vec<_>>().join(","))
+        });
+
+        let result = writeln!(
+            self.out,
+            "{{\"rank\":{},\"time_us\":{},\"lamport\":{},\"vector\":{},\"kind\":\"{}\",\"fork\":{},\"peer\":{},\"before\":{},\"after\":{},\"waited_us\":{}}}",
+            event.rank, event.time_us, event.lamport, vector, event.kind.name(), fork, peer,
+            fork_state(&event.before), fork_state(&event.after), waited
+        ).and_then(|_| self.out.flush());
+
+        if let Err(err) = result {
+            eprintln!("[{}] failed to write event log => {}", event.rank, err);
+        }
+    }
+}
+
+/// Where event timestamps come from: the wall clock since the run started, or the virtual clock
+/// of the simulator.
+pub enum Timebase {
+    Wall(Instant),
+    Virtual(Rc<Cell<Duration>>),
+}
+
+impl Timebase {
+    fn elapsed(&self) -> Duration {
+        match self {
+            Timebase::Wall(start) => start.elapsed(),
+            Timebase::Virtual(now) => now.get(),
+        }
+    }
+}
+
+/// Stamps events with the philosopher's rank and the time since the run started, then hands
+/// them to the configured sink. It also times every request from its arrival until the fork is
+/// given to the neighbour who sent it.
+pub struct EventLog {
+    rank: i32,
+    timebase: Timebase,
+    sink: Box<dyn EventSink>,
+    /// When the pending request of each neighbour arrived, in `time_us`.
+    requests: BTreeMap<i32, u128>,
+}
+
+impl EventLog {
+    pub fn new(rank: i32, start: Instant, sink: Box<dyn EventSink>) -> Self {
+        Self::with_timebase(rank, Timebase::Wall(start), sink)
+    }
+
+    pub fn with_timebase(rank: i32, timebase: Timebase, sink: Box<dyn EventSink>) -> Self {
+        Self { rank, timebase, sink, requests: BTreeMap::new() }
+    }
+
+    pub fn close(&mut self) {
+        self.sink.close();
+    }
+
+    pub fn emit(&mut self, clock: &Clock, kind: EventKind) {
+        self.record(clock, kind, None, None, None, None);
+    }
+
+    pub fn emit_peer(&mut self, clock: &Clock, kind: EventKind, peer: i32) {
+        self.record(clock, kind, Some(peer), None, None, None);
+    }
+
+    pub fn emit_fork(&mut self, clock: &Clock, kind: EventKind, peer: i32, fork: ForkId, before: ForkState, after: ForkState) {
+        self.record(clock, kind, Some(peer), Some(fork), Some(before), Some(after));
+    }
+
+    fn record(&mut self, clock: &Clock, kind: EventKind, peer: Option<i32>, fork: Option<ForkId>, before: Option<ForkState>, after: Option<ForkState>) {
+        let stamp = clock.stamp();
+        let time_us = self.timebase.elapsed().as_micros();
+        let waited_us = match (kind, peer) {
+            (EventKind::RequestReceived, Some(peer)) => {
+                self.requests.entry(peer).or_insert(time_us);
+                None
+            }
+            (EventKind::ForkGiven, Some(peer)) => self.requests.remove(&peer).map(|since| time_us - since),
+            _ => None,
+        };
+        let event = Event {
+            rank: self.rank,
+            time_us,
+            lamport: stamp.lamport,
+            vector: stamp.vector,
+            kind,
+            fork,
+            peer,
+            before,
+            after,
+            waited_us,
+        };
+        self.sink.record(&event);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{ config::Timing, transport::Transport, wire::Envelope, Message };
+use rand::{ rngs::StdRng, Rng, SeedableRng };
+use std::{ cell::RefCell, collections::{ BTreeMap, VecDeque }, mem, str::FromStr, time::Instant };
+
+/// What can happen to a single message on its way, short of arriving as it was sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Arrives late, and everything sent after it to the same rank waits behind it.
+    Delay(Timing),
+    /// Arrives after the next message to the same rank.
+    Reorder,
+    /// Arrives, and a copy of it arrives again after the next message to the same rank.
+    Duplicate,
+    /// Never arrives.
+    Drop,
+}
+
+/// Which faults hit how many messages, as given by `--faults` and `--fault-on`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    /// Each fault with the probability that it hits a message. They add up to at most 1.
+    pub odds: Vec<(Fault, f64)>,
+    /// The names of the messages that can be hit, see `Message::name`, or empty for all of them.
+    pub messages: Vec<String>,
+}
+
+impl Faults {
+    pub fn is_empty(&self) -> bool {
+        self.odds.is_empty()
+    }
+
+    pub fn delays(&self) -> bool {
+        self.odds.iter().any(|(fault, _)| matches!(fault, Fault::Delay(_)))
+    }
+
+    pub fn without_delays(&self) -> Self {
+        let odds = self.odds.iter().filter(|(fault, _)| !matches!(fault, Fault::Delay(_))).cloned().collect();
+        Faults { odds, messages: self.messages.clone() }
+    }
+
+    /// At most one fault per message, drawn from `rng` whether the message can be hit or not, so
+    /// that `--fault-on` changes which messages are hit but not the draws for the others.
+    fn pick<R: Rng + ?Sized>(&self, buf: &[u8], rng: &mut R) -> Option<&Fault> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut roll: f64 = rng.random();
+        let name = Envelope::try_from(buf).ok()?.message.name();
+        if !self.messages.is_empty() && !self.messages.iter().any(|message| message == name) {
+            return None;
+        }
+        for (fault, odds) in &self.odds {
+            if roll < *odds {
+                return Some(fault);
+            }
+            roll -= odds;
+        }
+        None
+    }
+}
+
+impl FromStr for Faults {
+    type Err = String;
+
+    /// A list like `drop:0.01,duplicate:0.05,reorder:0.1,delay:0.2:exp:5ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut odds = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let invalid = || format!("invalid fault => {}", entry);
+            let mut parts = entry.splitn(3, ':');
+            let (kind, probability, delay) = (parts.next().unwrap(), parts.next().ok_or_else(invalid)?, parts.next());
+            let probability: f64 = probability.parse().ok().filter(|probability| (0.0..=1.0).contains(probability)).ok_or_else(invalid)?;
+            let fault = match (kind, delay) {
+                ("delay", Some(delay)) => Fault::Delay(delay.parse()?),
+                ("reorder", None) => Fault::Reorder,
+                ("duplicate", None) => Fault::Duplicate,
+                ("drop", None) => Fault::Drop,
+                _ => return Err(invalid()),
+            };
+            odds.push((fault, probability));
+        }
+        if odds.iter().map(|(_, odds)| odds).sum::<f64>() > 1.0 {
+            return Err(format!("fault probabilities add up to more than 1 => {}", s));
+        }
+        Ok(Faults { odds, messages: Vec::new() })
+    }
+}
+
+/// The comma-separated message names of `--fault-on`.
+pub fn parse_messages(s: &str) -> Result<Vec<String>, String> {
+    let known = [
+        Message::GIVE(0), Message::REQUEST(0), Message::DONE, Message::ASK, Message::GRANT,
+        Message::RELEASE, Message::TOKEN, Message::FINAL, Message::THIRST(0), Message::BOTTLE(0),
+    ].map(|message| message.name());
+    s.split(',').map(str::trim)
+        .map(|name| if known.contains(&name) { Ok(name.to_string()) } else { Err(format!("invalid message => {}", name)) })
+        .collect()
+}
+
+/// The ranks draw from `seed + rank` and the simulator's scheduler from `seed - 1`, so the faults
+/// hitting what `rank` sends take a stream of their own.
+pub fn seed_for(seed: u64, size: i32, rank: i32) -> u64 {
+    seed.wrapping_add((size + rank) as u64)
+}
+
+/// What one rank holds back from another.
+#[derive(Default)]
+struct Outbox {
+    /// Sent on once due, in order, so the first one holds back the rest.
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+    /// Sent on right after the next message, or at the latest when `flush` is called.
+    behind: Vec<Vec<u8>>,
+}
+
+struct Held {
+    rng: StdRng,
+    outboxes: BTreeMap<i32, Outbox>,
+}
+
+/// Sits between a philosopher and its transport and lets `Faults` hit what it sends. Whatever is
+/// held back goes out at the latest when the philosopher waits for messages again, or when the
+/// transport is dropped, so a fault never outlives the run.
+pub struct FaultyTransport<T: Transport> {
+    inner: T,
+    faults: Faults,
+    held: RefCell<Held>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    /// Draws the faults from `seed`, see `seed_for`.
+    pub fn new(inner: T, faults: Faults, seed: u64) -> Self {
+        Self { inner, faults, held: RefCell::new(Held { rng: StdRng::seed_from_u64(seed), outboxes: BTreeMap::new() }) }
+    }
+
+    /// Sends `buf` now, unless earlier messages to `dest` are still delayed.
+    fn pass(&self, outbox: &mut Outbox, dest: i32, buf: Vec<u8>) {
+        match outbox.delayed.back() {
+            Some(&(due, _)) => outbox.delayed.push_back((due, buf)),
+            None => self.inner.send(dest, &buf),
+        }
+    }
+
+    /// Sends `buf`, followed by whatever was waiting for the next message to `dest`.
+    fn forward(&self, outbox: &mut Outbox, dest: i32, buf: Vec<u8>) {
+        let behind = mem::take(&mut outbox.behind);
+        self.pass(outbox, dest, buf);
+        for buf in behind {
+            self.pass(outbox, dest, buf);
+        }
+    }
+
+    /// Sends on the delayed messages that are due at `now`, or all of them without `now`.
+    fn release(&self, now: Option<Instant>) {
+        for (&dest, outbox) in self.held.borrow_mut().outboxes.iter_mut() {
+            while outbox.delayed.front().is_some_and(|&(due, _)| now.is_none_or(|now| due <= now)) {
+                let (_, buf) = outbox.delayed.pop_front().unwrap();
+                self.inner.send(dest, &buf);
+            }
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.held.borrow().outboxes.values().filter_map(|outbox| outbox.delayed.front().map(|&(due, _)| due)).min()
+    }
+
+    /// Sends on the messages waiting for a next one to the same rank, which will not come before the
+    /// philosopher has heard from someone. The simulator calls this after every step, `receive`
+    /// before waiting.
+    pub fn flush(&self) {
+        let mut held = self.held.borrow_mut();
+        for (&dest, outbox) in held.outboxes.iter_mut() {
+            for buf in mem::take(&mut outbox.behind) {
+                self.pass(outbox, dest, buf);
+            }
+        }
+        drop(held);
+        self.release(Some(Instant::now()));
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    fn send(&self, dest: i32, buf: &[u8]) {
+        let mut held = self.held.borrow_mut();
+        let Held { rng, outboxes } = &mut *held;
+        let fault = self.faults.pick(buf, rng);
+        let outbox = outboxes.entry(dest).or_default();
+        match fault {
+            Some(Fault::Delay(delay)) => {
+                let due = Instant::now() + delay.sample(rng);
+                // Nothing overtakes a delayed message, so it waits for the ones before it too.
+                let due = outbox.delayed.back().map_or(due, |&(before, _)| due.max(before));
+                outbox.delayed.push_back((due, buf.to_vec()));
+            }
+            Some(Fault::Reorder) => outbox.behind.push(buf.to_vec()),
+            Some(Fault::Duplicate) => {
+                self.forward(outbox, dest, buf.to_vec());
+                outbox.behind.push(buf.to_vec());
+            }
+            Some(Fault::Drop) => {}
+            None => self.forward(outbox, dest, buf.to_vec()),
+        }
+    }
+
+    fn receive(&self) -> (Vec<u8>, i32) {
+        self.flush();
+        while let Some(due) = self.next_due() {
+            if let Some(received) = self.inner.receive_until(due) {
+                return received;
+            }
+            self.release(Some(Instant::now()));
+        }
+        self.inner.receive()
+    }
+
+    fn receive_until(&self, deadline: Instant) -> Option<(Vec<u8>, i32)> {
+        self.flush();
+        loop {
+            let wake = self.next_due().map_or(deadline, |due| due.min(deadline));
+            if let Some(received) = self.inner.receive_until(wake) {
+                return Some(received);
+            }
+            self.release(Some(Instant::now()));
+            if Instant::now() >= deadline {
+                return None;
+            }
+        }
+    }
+}
+
+impl<T: Transport> Drop for FaultyTransport<T> {
+    fn drop(&mut self) {
+        self.flush();
+        self.release(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ clock::Stamp, receive_until, transport::{ self, ChannelTransport } };
+    use std::{ thread, time::Duration };
+
+    fn send(transport: &dyn Transport, dest: i32, seq: u64, message: Message) {
+        transport.send(dest, &Envelope::new(seq, message, Stamp { lamport: seq, vector: None }, 0).encode());
+    }
+
+    /// The sequence numbers that have arrived at `transport` by now.
+    fn arrived(transport: &ChannelTransport) -> Vec<u64> {
+        let mut seqs = Vec::new();
+        while let Some((envelope, _)) = receive_until(transport, Instant::now()).unwrap() {
+            seqs.push(envelope.seq);
+        }
+        seqs
+    }
+
+    fn always(fault: Fault) -> Faults {
+        Faults { odds: vec![(fault, 1.0)], messages: vec!["give".to_string()] }
+    }
+
+    #[test]
+    fn faults_parse_and_refuse_to_add_up_to_more_than_one() {
+        let faults: Faults = "drop:0.01, duplicate:0.05,reorder:0.1,delay:0.2:exp:5ms".parse().unwrap();
+        assert_eq!(faults.odds, vec![
+            (Fault::Drop, 0.01), (Fault::Duplicate, 0.05), (Fault::Reorder, 0.1),
+            (Fault::Delay(Timing::Exponential(Duration::from_millis(5))), 0.2),
+        ]);
+        assert!(faults.delays());
+        assert!("".parse::<Faults>().unwrap().is_empty());
+
+        assert_eq!(parse_messages("give, request").unwrap(), ["give", "request"]);
+        assert!(parse_messages("give,fork").is_err());
+
+        for invalid in ["drop", "drop:2", "drop:0.1:5ms", "delay:0.1", "delay:0.1:5ms", "lose:0.1", "drop:0.6,reorder:0.5"] {
+            assert!(invalid.parse::<Faults>().is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    /// Sends a give, a request and another give through a transport that `fault` may hit on gives.
+    fn table(fault: Fault) -> (ChannelTransport, FaultyTransport<ChannelTransport>) {
+        let mut transports = transport::channels(2);
+        let inbox = transports.pop().unwrap();
+        let faulty = FaultyTransport::new(transports.pop().unwrap(), always(fault), 0);
+        for (seq, message) in [Message::GIVE(0), Message::REQUEST(0), Message::GIVE(0)].into_iter().enumerate() {
+            send(&faulty, 1, seq as u64, message);
+        }
+        (inbox, faulty)
+    }
+
+    #[test]
+    fn every_fault_does_what_it_says_to_the_messages_it_may_hit() {
+        // What arrives right away, and what only once the sender waits for messages again.
+        for (fault, sent, flushed) in [(Fault::Drop, vec![1], vec![]), (Fault::Duplicate, vec![0, 1, 0, 2], vec![2]), (Fault::Reorder, vec![1, 0], vec![2])] {
+            let (inbox, faulty) = table(fault.clone());
+            assert_eq!(arrived(&inbox), sent, "{:?}", fault);
+            faulty.flush();
+            assert_eq!(arrived(&inbox), flushed, "{:?}", fault);
+        }
+
+        // The delayed give holds back the request behind it until it is due.
+        let (inbox, faulty) = table(Fault::Delay(Timing::Fixed(Duration::from_millis(20))));
+        faulty.flush();
+        assert_eq!(arrived(&inbox), []);
+        thread::sleep(Duration::from_millis(20));
+        faulty.flush();
+        assert_eq!(arrived(&inbox), [0, 1, 2]);
+
+        // Nothing is lost to a delay when the sender leaves first.
+        let (inbox, faulty) = table(Fault::Delay(Timing::Fixed(Duration::from_secs(60))));
+        drop(faulty);
+        assert_eq!(arrived(&inbox), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,728 @@
+//! The dining philosophers protocol without the MPI process around it. A `Philosopher` holds the
+//! forks and answers the messages of its neighbours, a `DiningStrategy` decides when it may eat,
+//! and both talk through a `Transport`, so they can be driven by `runner`, the simulator, the
+//! model checker or any other tool.
+
+pub mod clock;
+pub mod config;
+pub mod dashboard;
+pub mod error;
+pub mod event;
+pub mod fault;
+pub mod model_check;
+pub mod runner;
+pub mod sim;
+pub mod stats;
+pub mod strategy;
+pub mod topology;
+pub mod transport;
+pub mod wire;
+
+use clock::Clock;
+use error::PhilosopherError;
+use event::{ EventKind, EventLog };
+use stats::Stats;
+use topology::Graph;
+use transport::Transport;
+use wire::Envelope;
+use std::{ collections::BTreeMap, fmt, str::FromStr, time::{ Duration, Instant } };
+
+#[derive(Debug)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum ForkState {
+    MISSING,
+    CLEAN,
+    DIRTY,
+}
+
+impl FromStr for ForkState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "missing" => Ok(ForkState::MISSING),
+            "clean" => Ok(ForkState::CLEAN),
+            "dirty" => Ok(ForkState::DIRTY),
+            _ => Err(format!("invalid fork state => {}", s)),
+        }
+    }
+}
+
+/// Forks are the edges of the conflict graph and numbered by their position in `Graph::edges`.
+pub type ForkId = u32;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Message {
+    GIVE(ForkId),
+    REQUEST(ForkId),
+    DONE,
+    // Waiter: ask the waiter for a seat, get one, and give it back after eating.
+    ASK,
+    GRANT,
+    RELEASE,
+    // Token ring: only the holder of the token may eat. The final token tells everyone it is gone.
+    TOKEN,
+    FINAL,
+    // Drinking: ask for the bottle on an edge, and hand it over.
+    THIRST(ForkId),
+    BOTTLE(ForkId),
+}
+
+impl Message {
+    /// What `--fault-on` calls this kind of message.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::GIVE(_) => "give",
+            Message::REQUEST(_) => "request",
+            Message::DONE => "done",
+            Message::ASK => "ask",
+            Message::GRANT => "grant",
+            Message::RELEASE => "release",
+            Message::TOKEN => "token",
+            Message::FINAL => "final",
+            Message::THIRST(_) => "thirst",
+            Message::BOTTLE(_) => "bottle",
+        }
+    }
+}
+
+pub fn receive(transport: &dyn Transport) -> Result<(Envelope, i32), PhilosopherError> {
+    let (buf, sender) = transport.receive();
+    decode(&buf, sender)
+}
+
+/// Like `receive`, but returns `None` once `deadline` has passed without a message.
+pub fn receive_until(transport: &dyn Transport, deadline: Instant) -> Result<Option<(Envelope, i32)>, PhilosopherError> {
+    transport.receive_until(deadline).map(|(buf, sender)| decode(&buf, sender)).transpose()
+}
+
+fn decode(buf: &[u8], sender: i32) -> Result<(Envelope, i32), PhilosopherError> {
+    let envelope = Envelope::try_from(buf).map_err(|err| PhilosopherError::MalformedMessage { sender, err })?;
+    Ok((envelope, sender))
+}
+
+/// Where a philosopher is in its cycle. It only ever moves on to the next phase, and only eats
+/// with every fork in hand, see `become_hungry`, `start_eating` and `eat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Thinking,
+    Hungry,
+    Eating,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::Thinking => write!(f, "thinking"),
+            Phase::Hungry => write!(f, "hungry"),
+            Phase::Eating => write!(f, "eating"),
+        }
+    }
+}
+
+/// The fork shared with one neighbour, and whether that neighbour is waiting for it.
+#[derive(Debug, Clone)]
+pub(crate) struct Fork {
+    pub(crate) id: ForkId,
+    pub(crate) state: ForkState,
+    pub(crate) requested: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Philosopher {
+    pub(crate) rank: i32,
+    pub(crate) size: i32,
+    /// Keyed by the neighbour the fork is shared with.
+    pub(crate) forks: BTreeMap<i32, Fork>,
+    pub(crate) finished_peers: i32,
+    pub(crate) meals: u32,
+    /// No fork leaves the table while we are `Eating`.
+    pub(crate) phase: Phase,
+    pub(crate) next_seq: u64,
+    pub(crate) clock: Clock,
+    pub(crate) stats: Stats,
+}
+
+impl Philosopher {
+    /// Holds, dirty, every fork `graph` hands to `rank` at the start.
+    pub fn new(graph: &Graph, rank: i32, clock: Clock) -> Self {
+        let forks = graph.forks(rank).into_iter()
+            .map(|(neighbour, id)| {
+                let state = if graph.initial_holder(id) == rank { ForkState::DIRTY } else { ForkState::MISSING };
+                (neighbour, Fork { id, state, requested: false })
+            })
+            .collect();
+
+        Self {
+            rank,
+            size: graph.size(),
+            forks,
+            finished_peers: 0,
+            meals: 0,
+            phase: Phase::Thinking,
+            next_seq: 0,
+            clock,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn log_initial_forks(&self, log: &mut EventLog) {
+        for (&neighbour, fork) in &self.forks {
+            log.emit_fork(&self.clock, EventKind::ForkInit, neighbour, fork.id, fork.state, fork.state);
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    fn transition(&mut self, from: Phase, to: Phase) -> Result<(), PhilosopherError> {
+        if self.phase != from {
+            return Err(PhilosopherError::IllegalTransition { from: self.phase, to });
+        }
+        self.phase = to;
+        Ok(())
+    }
+
+    pub fn become_hungry(&mut self) -> Result<(), PhilosopherError> {
+        self.transition(Phase::Thinking, Phase::Hungry)
+    }
+
+    /// Only a hungry philosopher holding every fork may start eating.
+    pub fn start_eating(&mut self) -> Result<(), PhilosopherError> {
+        let missing: Vec<_> = self.neighbours().filter(|&neighbour| self.fork(neighbour) == ForkState::MISSING).collect();
+        if self.phase == Phase::Hungry && !missing.is_empty() {
+            return Err(PhilosopherError::EatingWithoutForks { missing });
+        }
+        self.transition(Phase::Hungry, Phase::Eating)
+    }
+
+    /// Finishes the meal, leaving every fork dirty, and goes back to thinking.
+    pub fn eat(&mut self) -> Result<(), PhilosopherError> {
+        self.transition(Phase::Eating, Phase::Thinking)?;
+        for fork in self.forks.values_mut() {
+            fork.state = ForkState::DIRTY;
+        }
+        self.meals += 1;
+        Ok(())
+    }
+
+    /// The neighbours we share a fork with, in ascending order.
+    pub fn neighbours(&self) -> impl Iterator<Item = i32> + '_ {
+        self.forks.keys().copied()
+    }
+
+    /// Panics if we share no fork with `neighbour`.
+    pub fn fork(&self, neighbour: i32) -> ForkState {
+        self.forks[&neighbour].state
+    }
+
+    pub fn fork_id(&self, neighbour: i32) -> ForkId {
+        self.forks[&neighbour].id
+    }
+
+    /// Whether `neighbour` has asked for our shared fork and is still waiting for it.
+    pub fn requested(&self, neighbour: i32) -> bool {
+        self.forks[&neighbour].requested
+    }
+
+    pub fn rank(&self) -> i32 {
+        self.rank
+    }
+
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    pub fn meals(&self) -> u32 {
+        self.meals
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Counts a meal, once the driver knows how long we were hungry for it.
+    pub fn ate_after(&mut self, hungry: Duration) {
+        self.stats.ate_after(hungry);
+    }
+
+    /// One flag per fork of `graph`, set for the forks we hold, as `Graph::check_initial_forks`
+    /// expects them from every rank.
+    pub fn held_forks(&self, graph: &Graph) -> Vec<bool> {
+        let mut held = vec![false; graph.edges().len()];
+        for fork in self.forks.values().filter(|fork| fork.state != ForkState::MISSING) {
+            held[fork.id as usize] = true;
+        }
+        held
+    }
+
+    pub fn check_forks_missing(&self) -> bool {
+        self.forks.values().any(|fork| fork.state == ForkState::MISSING)
+    }
+
+    /// Checks that `fork` is the one we share with `sender`, and returns `sender` as the neighbour.
+    pub fn neighbour_of(&self, fork: ForkId, sender: i32) -> Result<i32, PhilosopherError> {
+        match self.forks.get(&sender) {
+            Some(shared) if shared.id == fork => Ok(sender),
+            _ => Err(PhilosopherError::UnknownFork { fork, sender }),
+        }
+    }
+
+    pub fn send(&mut self, transport: &dyn Transport, dest: i32, msg: Message) {
+        let envelope = Envelope::new(self.next_seq, msg, self.clock.on_send(), self.meals);
+        self.next_seq += 1;
+        self.stats.messages_sent += 1;
+        transport.send(dest, &envelope.encode());
+    }
+
+    pub fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<Option<i32>, PhilosopherError> {
+        self.clock.on_receive(&envelope.stamp);
+        let msg_type = &envelope.message;
+        match msg_type {
+            Message::GIVE(_) => self.received_fork(msg_type, sender, log).map(Some),
+            Message::REQUEST(_) => {
+                self.respond_to_msg_request(msg_type, sender, transport, log)?;
+                Ok(None)
+            }
+            Message::DONE => {
+                self.peer_done(sender, log);
+                Ok(None)
+            }
+            _ => Err(PhilosopherError::UnexpectedMessage { context: "exchanging clean and dirty forks", message: msg_type.clone(), sender }),
+        }
+    }
+
+    pub fn peer_done(&mut self, sender: i32, log: &mut EventLog) {
+        log.emit_peer(&self.clock, EventKind::PeerDone, sender);
+        self.finished_peers += 1;
+    }
+
+    pub fn announce_done(&mut self, transport: &dyn Transport) {
+        let rank = self.rank;
+        for peer in (0..self.size).filter(|&peer| peer != rank) {
+            self.send(transport, peer, Message::DONE);
+        }
+    }
+
+    pub fn all_peers_finished(&self) -> bool {
+        self.finished_peers == self.size - 1
+    }
+
+    /// Takes the fork `sender` gave us, clean, and returns `sender` as the neighbour it came from.
+    pub fn received_fork(&mut self, msg_type: &Message, sender: i32, log: &mut EventLog) -> Result<i32, PhilosopherError> {
+        let Message::GIVE(fork) = msg_type else {
+            return Err(PhilosopherError::UnexpectedMessage { context: "receiving a fork", message: msg_type.clone(), sender });
+        };
+
+        let neighbour = self.neighbour_of(*fork, sender)?;
+        let shared = self.forks.get_mut(&neighbour).unwrap();
+        log.emit_fork(&self.clock, EventKind::ForkReceived, neighbour, shared.id, shared.state, ForkState::CLEAN);
+        shared.state = ForkState::CLEAN;
+        self.stats.forks_received += 1;
+        Ok(neighbour)
+    }
+
+    pub fn respond_to_msg_request(&mut self, msg_type: &Message, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        let Message::REQUEST(fork) = msg_type else {
+            return Err(PhilosopherError::UnexpectedMessage { context: "answering a request", message: msg_type.clone(), sender });
+        };
+
+        let neighbour = self.neighbour_of(*fork, sender)?;
+        let keep = self.fork(neighbour) != ForkState::DIRTY;
+        self.give_or_defer(neighbour, keep, transport, log);
+        Ok(())
+    }
+
+    pub fn respond_to_existing_requests(&mut self, transport: &dyn Transport, log: &mut EventLog) {
+        let requested: Vec<_> = self.forks.iter().filter(|(_, fork)| fork.requested).map(|(&neighbour, _)| neighbour).collect();
+        for neighbour in requested {
+            self.give_fork(neighbour, transport, log);
+        }
+    }
+
+    pub fn give_fork(&mut self, neighbour: i32, transport: &dyn Transport, log: &mut EventLog) {
+        let fork = self.fork_id(neighbour);
+        self.send(transport, neighbour, Message::GIVE(fork));
+        let shared = self.forks.get_mut(&neighbour).unwrap();
+        log.emit_fork(&self.clock, EventKind::ForkGiven, neighbour, fork, shared.state, ForkState::MISSING);
+        shared.state = ForkState::MISSING;
+        shared.requested = false;
+        self.stats.forks_sent += 1;
+    }
+
+    /// Hands the fork over now, or remembers the request for `respond_to_existing_requests` if we
+    /// have to `keep` it. Strategies other than Chandy–Misra decide this without clean and dirty.
+    /// Whatever they decide, a fork we are eating with stays until the meal is over.
+    pub fn give_or_defer(&mut self, neighbour: i32, keep: bool, transport: &dyn Transport, log: &mut EventLog) {
+        let (fork, state) = (self.fork_id(neighbour), self.fork(neighbour));
+        log.emit_fork(&self.clock, EventKind::RequestReceived, neighbour, fork, state, state);
+        if keep || self.phase == Phase::Eating {
+            self.forks.get_mut(&neighbour).unwrap().requested = true;
+            self.stats.requests_deferred += 1;
+        } else {
+            self.give_fork(neighbour, transport, log);
+        }
+    }
+
+    pub fn request_from(&mut self, neighbour: i32, transport: &dyn Transport, log: &mut EventLog) {
+        let fork = self.fork_id(neighbour);
+        self.send(transport, neighbour, Message::REQUEST(fork));
+        let state = self.fork(neighbour);
+        log.emit_fork(&self.clock, EventKind::RequestSent, neighbour, fork, state, state);
+    }
+
+    pub fn request_missing_forks(&mut self, transport: &dyn Transport, log: &mut EventLog) {
+        let missing: Vec<_> = self.neighbours().filter(|&neighbour| self.fork(neighbour) == ForkState::MISSING).collect();
+        for neighbour in missing {
+            self.request_from(neighbour, transport, log);
+        }
+    }
+
+    /// Requests the first missing fork, in the order of the neighbours, and returns who was asked.
+    pub fn request_fork(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<i32, PhilosopherError> {
+        let neighbour = self.neighbours().find(|&neighbour| self.fork(neighbour) == ForkState::MISSING).ok_or(PhilosopherError::NoForkMissing)?;
+        self.request_from(neighbour, transport, log);
+        Ok(neighbour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::Stamp;
+    use event::NullSink;
+    use rand::{ rngs::StdRng, Rng, SeedableRng };
+    use std::time;
+    use topology::Topology;
+    use transport::ChannelTransport;
+
+    const STATES: [ForkState; 3] = [ForkState::MISSING, ForkState::CLEAN, ForkState::DIRTY];
+
+    fn null_log() -> EventLog {
+        EventLog::new(0, time::Instant::now(), Box::new(NullSink))
+    }
+
+    fn envelope(message: Message) -> Envelope {
+        Envelope::new(0, message, Stamp { lamport: 0, vector: None }, 0)
+    }
+
+    fn ring(size: i32) -> Graph {
+        Topology::Ring.graph(size).unwrap()
+    }
+
+    /// Rank 1 of a ring of three, between [0] and [2], holding the given forks. It shares fork 0
+    /// with [0] and fork 2 with [2].
+    fn middle(zero: ForkState, two: ForkState) -> Philosopher {
+        let mut philosopher = Philosopher::new(&ring(3), 1, Clock::new(3, 1, false));
+        philosopher.forks.get_mut(&0).unwrap().state = zero;
+        philosopher.forks.get_mut(&2).unwrap().state = two;
+        philosopher
+    }
+
+    /// Everything that has been sent to `transport`, with the senders.
+    fn drain(transport: &ChannelTransport) -> Vec<(Message, i32)> {
+        let mut messages = Vec::new();
+        while let Some((envelope, sender)) = receive_until(transport, time::Instant::now()).unwrap() {
+            messages.push((envelope.message, sender));
+        }
+        messages
+    }
+
+    fn topologies() -> Vec<(Topology, i32)> {
+        let mut topologies: Vec<_> = (2..=8).map(|size| (Topology::Ring, size)).collect();
+        topologies.extend([
+            (Topology::Complete, 2), (Topology::Complete, 5), (Topology::Star, 2), (Topology::Star, 6),
+            (Topology::Grid { rows: 1, cols: 4 }, 4), (Topology::Grid { rows: 3, cols: 3 }, 9),
+        ]);
+        topologies
+    }
+
+    #[test]
+    fn new_wires_up_one_fork_per_edge_for_every_position() {
+        for (topology, size) in topologies() {
+            let graph = topology.graph(size).unwrap();
+            let philosophers: Vec<_> = (0..size).map(|rank| Philosopher::new(&graph, rank, Clock::new(size, rank, false))).collect();
+
+            for (rank, philosopher) in (0..size).zip(&philosophers) {
+                assert_eq!(philosopher.neighbours().collect::<Vec<_>>(), graph.neighbours(rank), "neighbours of [{}] on a {} of {}", rank, topology, size);
+                for neighbour in philosopher.neighbours() {
+                    assert_eq!(Some(philosopher.fork_id(neighbour)), graph.fork(rank, neighbour));
+                    assert!(!philosopher.requested(neighbour));
+                }
+                assert_eq!((philosopher.meals, philosopher.finished_peers), (0, 0));
+            }
+
+            // Every fork starts out dirty with the lower ranked of its two ends, and only there.
+            for (&(low, high), fork) in graph.edges().iter().zip(0..) {
+                let (low, high) = (&philosophers[low as usize], &philosophers[high as usize]);
+                assert_eq!(low.fork_id(high.rank), fork);
+                assert_eq!((low.fork(high.rank), high.fork(low.rank)), (ForkState::DIRTY, ForkState::MISSING), "fork {} on a {} of {}", fork, topology, size);
+            }
+        }
+
+        // On a ring this is the original seating: [0] holds both its forks and the last rank none.
+        for size in 3..=8 {
+            let graph = ring(size);
+            for rank in 0..size {
+                let philosopher = Philosopher::new(&graph, rank, Clock::new(size, rank, false));
+                let (left, right) = ((rank + 1) % size, (rank + size - 1) % size);
+                let expected = if rank == 0 {
+                    (ForkState::DIRTY, ForkState::DIRTY)
+                } else if rank == size - 1 {
+                    (ForkState::MISSING, ForkState::MISSING)
+                } else {
+                    (ForkState::DIRTY, ForkState::MISSING)
+                };
+                assert_eq!((philosopher.fork(left), philosopher.fork(right)), expected, "forks of [{}] of {}", rank, size);
+            }
+        }
+    }
+
+    #[test]
+    fn received_fork_cleans_the_matching_fork_whatever_it_was() {
+        for (sender, fork) in [(0, 0), (2, 2)] {
+            for before in STATES {
+                for other in STATES {
+                    let mut philosopher = if sender == 0 { middle(before, other) } else { middle(other, before) };
+
+                    let received = philosopher.received_fork(&Message::GIVE(fork), sender, &mut null_log()).unwrap();
+
+                    assert_eq!(received, sender);
+                    let untouched = philosopher.fork(2 - sender);
+                    assert_eq!((philosopher.fork(sender), untouched), (ForkState::CLEAN, other), "fork from [{}] was {:?}", sender, before);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn received_fork_rejects_strangers_and_other_messages() {
+        let mut philosopher = middle(ForkState::MISSING, ForkState::MISSING);
+        let mut log = null_log();
+
+        assert!(matches!(philosopher.received_fork(&Message::GIVE(2), 0, &mut log), Err(PhilosopherError::UnknownFork { fork: 2, sender: 0 })));
+        assert!(matches!(philosopher.received_fork(&Message::GIVE(1), 2, &mut log), Err(PhilosopherError::UnknownFork { fork: 1, sender: 2 })));
+        assert!(matches!(philosopher.received_fork(&Message::REQUEST(2), 2, &mut log), Err(PhilosopherError::UnexpectedMessage { .. })));
+        assert_eq!((philosopher.fork(0), philosopher.fork(2)), (ForkState::MISSING, ForkState::MISSING));
+    }
+
+    #[test]
+    fn respond_to_msg_request_gives_dirty_forks_and_defers_the_rest() {
+        for (sender, fork) in [(0, 0), (2, 2)] {
+            for before in STATES {
+                for other in STATES {
+                    let transports = transport::channels(3);
+                    let mut philosopher = if sender == 0 { middle(before, other) } else { middle(other, before) };
+
+                    philosopher.respond_to_msg_request(&Message::REQUEST(fork), sender, &transports[1], &mut null_log()).unwrap();
+
+                    let (mine, deferred, untouched) = (philosopher.fork(sender), philosopher.requested(sender), philosopher.fork(2 - sender));
+                    let sent = drain(&transports[sender as usize]);
+                    assert_eq!(untouched, other);
+                    if before == ForkState::DIRTY {
+                        assert_eq!(sent, vec![(Message::GIVE(fork), 1)], "fork for [{}] was dirty", sender);
+                        assert_eq!((mine, deferred), (ForkState::MISSING, false));
+                    } else {
+                        assert!(sent.is_empty(), "fork for [{}] was {:?} but got sent", sender, before);
+                        assert_eq!((mine, deferred), (before, true));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn phases_follow_the_cycle_and_eating_needs_every_fork() {
+        let mut philosopher = middle(ForkState::DIRTY, ForkState::MISSING);
+        assert_eq!(philosopher.phase(), Phase::Thinking);
+        assert!(matches!(philosopher.start_eating(), Err(PhilosopherError::IllegalTransition { from: Phase::Thinking, to: Phase::Eating })));
+        assert!(matches!(philosopher.eat(), Err(PhilosopherError::IllegalTransition { from: Phase::Thinking, to: Phase::Thinking })));
+
+        philosopher.become_hungry().unwrap();
+        assert!(matches!(philosopher.become_hungry(), Err(PhilosopherError::IllegalTransition { from: Phase::Hungry, .. })));
+        assert!(matches!(philosopher.start_eating(), Err(PhilosopherError::EatingWithoutForks { missing }) if missing == [2]));
+        assert!(matches!(philosopher.eat(), Err(PhilosopherError::IllegalTransition { from: Phase::Hungry, .. })));
+        assert_eq!((philosopher.phase(), philosopher.meals), (Phase::Hungry, 0));
+        assert_eq!((philosopher.fork(0), philosopher.fork(2)), (ForkState::DIRTY, ForkState::MISSING), "a refused meal touched the forks");
+
+        philosopher.forks.get_mut(&2).unwrap().state = ForkState::CLEAN;
+        philosopher.start_eating().unwrap();
+        assert_eq!(philosopher.phase(), Phase::Eating);
+        assert!(matches!(philosopher.become_hungry(), Err(PhilosopherError::IllegalTransition { from: Phase::Eating, .. })));
+        philosopher.eat().unwrap();
+        assert_eq!((philosopher.phase(), philosopher.meals), (Phase::Thinking, 1));
+        assert_eq!((philosopher.fork(0), philosopher.fork(2)), (ForkState::DIRTY, ForkState::DIRTY));
+    }
+
+    #[test]
+    fn requests_during_a_meal_wait_until_it_is_over() {
+        let transports = transport::channels(3);
+        let mut log = null_log();
+        let mut philosopher = middle(ForkState::DIRTY, ForkState::DIRTY);
+        philosopher.become_hungry().unwrap();
+        philosopher.start_eating().unwrap();
+
+        philosopher.respond_to_msg_request(&Message::REQUEST(0), 0, &transports[1], &mut log).unwrap();
+        philosopher.give_or_defer(2, false, &transports[1], &mut log);
+        assert!(drain(&transports[0]).is_empty() && drain(&transports[2]).is_empty(), "a fork left in the middle of a meal");
+        assert!(philosopher.requested(0) && philosopher.requested(2));
+
+        philosopher.eat().unwrap();
+        philosopher.respond_to_existing_requests(&transports[1], &mut log);
+        assert_eq!(drain(&transports[0]), vec![(Message::GIVE(0), 1)]);
+        assert_eq!(drain(&transports[2]), vec![(Message::GIVE(2), 1)]);
+    }
+
+    #[test]
+    fn respond_to_msg_request_rejects_strangers_and_other_messages() {
+        let transports = transport::channels(3);
+        let mut philosopher = middle(ForkState::DIRTY, ForkState::DIRTY);
+        let mut log = null_log();
+
+        assert!(matches!(
+            philosopher.respond_to_msg_request(&Message::REQUEST(0), 2, &transports[1], &mut log),
+            Err(PhilosopherError::UnknownFork { fork: 0, sender: 2 })
+        ));
+        assert!(matches!(
+            philosopher.respond_to_msg_request(&Message::GIVE(2), 2, &transports[1], &mut log),
+            Err(PhilosopherError::UnexpectedMessage { .. })
+        ));
+        assert!(drain(&transports[0]).is_empty() && drain(&transports[2]).is_empty());
+    }
+
+    /// Feeds one philosopher random but protocol-conforming sequences of requests, forks and meals,
+    /// and checks after every step that only dirty forks leave, and only towards whoever asked.
+    #[test]
+    fn random_event_sequences_keep_clean_forks_and_surrender_dirty_ones() {
+        let mut rng = StdRng::seed_from_u64(2024);
+        let topologies = [Topology::Ring, Topology::Complete, Topology::Star];
+
+        for _ in 0..500 {
+            let size = rng.random_range(2..=6);
+            let rank = rng.random_range(0..size);
+            let graph = topologies[rng.random_range(0..topologies.len())].graph(size).unwrap();
+            let transports = transport::channels(size);
+            let mut philosopher = Philosopher::new(&graph, rank, Clock::new(size, rank, false));
+            let mut log = null_log();
+            let mut meals = 0;
+            let neighbours = graph.neighbours(rank);
+            if neighbours.is_empty() {
+                continue;
+            }
+
+            for _ in 0..50 {
+                let before = philosopher.clone();
+                let neighbour = neighbours[rng.random_range(0..neighbours.len())];
+                let (fork, held, requested) = (before.fork_id(neighbour), before.fork(neighbour), before.requested(neighbour));
+
+                match rng.random_range(0..3) {
+                    // A neighbour only asks for a fork we hold, and only once until it gets it.
+                    0 if held != ForkState::MISSING && !requested => {
+                        philosopher.handle_message(&envelope(Message::REQUEST(fork)), neighbour, &transports[rank as usize], &mut log).unwrap();
+                        let given = drain(&transports[neighbour as usize]);
+                        if held == ForkState::DIRTY {
+                            assert_eq!(given, vec![(Message::GIVE(fork), rank)], "a dirty fork must be surrendered");
+                            assert_eq!(philosopher.fork(neighbour), ForkState::MISSING);
+                        } else {
+                            assert!(given.is_empty(), "a clean fork must never be given away");
+                            assert_eq!(philosopher.fork(neighbour), ForkState::CLEAN);
+                        }
+                    }
+                    1 if held == ForkState::MISSING => {
+                        let received = philosopher.handle_message(&envelope(Message::GIVE(fork)), neighbour, &transports[rank as usize], &mut log).unwrap();
+                        assert_eq!(received, Some(neighbour));
+                        assert_eq!(philosopher.fork(neighbour), ForkState::CLEAN);
+                    }
+                    2 if !before.check_forks_missing() => {
+                        philosopher.become_hungry().unwrap();
+                        philosopher.start_eating().unwrap();
+                        philosopher.eat().unwrap();
+                        meals += 1;
+                        assert!(neighbours.iter().all(|&neighbour| philosopher.fork(neighbour) == ForkState::DIRTY));
+                        philosopher.respond_to_existing_requests(&transports[rank as usize], &mut log);
+
+                        // Exactly the deferred requests are answered, now that the forks are dirty.
+                        for &neighbour in &neighbours {
+                            let given = drain(&transports[neighbour as usize]);
+                            let expected = if before.requested(neighbour) { vec![(Message::GIVE(before.fork_id(neighbour)), rank)] } else { Vec::new() };
+                            assert_eq!(given, expected, "answering [{}]", neighbour);
+                            assert!(!philosopher.requested(neighbour));
+                        }
+                    }
+                    _ => continue,
+                }
+
+                // Requests are only ever remembered for forks we still hold clean.
+                for &neighbour in &neighbours {
+                    let state = philosopher.fork(neighbour);
+                    assert!(!philosopher.requested(neighbour) || state == ForkState::CLEAN, "request of [{}] kept for {:?} fork", neighbour, state);
+                }
+                assert_eq!(philosopher.meals, meals);
+            }
+        }
+    }
+
+    /// Hands every queued message to its philosopher until no rank has anything left to read.
+    fn deliver_all(transports: &[ChannelTransport], mut handle: impl FnMut(usize, Envelope, i32)) {
+        loop {
+            let mut delivered = false;
+            for (rank, transport) in transports.iter().enumerate() {
+                while let Some((envelope, sender)) = receive_until(transport, time::Instant::now()).unwrap() {
+                    handle(rank, envelope, sender);
+                    delivered = true;
+                }
+            }
+            if !delivered {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn two_philosophers_alternate_without_deadlock() {
+        let transports = transport::channels(2);
+        let graph = ring(2);
+        let mut philosophers: Vec<_> = (0..2).map(|rank| Philosopher::new(&graph, rank, Clock::new(2, rank, false))).collect();
+        for philosopher in &mut philosophers {
+            philosopher.become_hungry().unwrap();
+        }
+        let mut log = EventLog::new(0, time::Instant::now(), Box::new(NullSink));
+        let mut awaiting: [Option<i32>; 2] = [None; 2];
+        let mut meals = Vec::new();
+
+        // Both philosophers are hungry all the time and follow the same steps as `dine`: request
+        // one missing fork, wait for it, and eat once nothing is missing.
+        for _ in 0..100 {
+            for rank in 0..2 {
+                if awaiting[rank].is_some() {
+                    continue;
+                }
+                let philosopher = &mut philosophers[rank];
+                if philosopher.check_forks_missing() {
+                    awaiting[rank] = Some(philosopher.request_fork(&transports[rank], &mut log).unwrap());
+                } else {
+                    meals.push(rank);
+                    philosopher.start_eating().unwrap();
+                    philosopher.eat().unwrap();
+                    philosopher.respond_to_existing_requests(&transports[rank], &mut log);
+                    philosopher.become_hungry().unwrap();
+                }
+            }
+
+            deliver_all(&transports, |to, envelope, from| {
+                let received = philosophers[to].handle_message(&envelope, from, &transports[to], &mut log).unwrap();
+                if received.is_some() && received == awaiting[to] {
+                    awaiting[to] = None;
+                }
+            });
+        }
+
+        assert!(meals.len() >= 20, "only {} meals before getting stuck", meals.len());
+        assert!(meals.windows(2).all(|pair| pair[0] != pair[1]), "meals did not alternate => {:?}", meals);
+    }
+}
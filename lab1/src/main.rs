@@ -1,242 +1,145 @@
-use mpi::{topology::SimpleCommunicator, traits::*};
-use rand::Rng;
-use core::panic;
-use std::{ thread, time };
-
-#[derive(Debug)]
-#[derive(PartialEq)]
-enum ForkState {
-    MISSING,
-    CLEAN,
-    DIRTY,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum Side {
-    LEFT,
-    RIGHT,
-}
+use lab1::{ config::Config, model_check, runner, sim, stats };
+#[cfg(feature = "mpi")]
+use lab1::{ config::LogFormat, dashboard, error::PhilosopherError, fault::{ self, FaultyTransport }, stats::Stats, transport::MpiTransport };
+#[cfg(feature = "mpi")]
+use mpi::{ topology::SimpleCommunicator, traits::*, Threading };
+use std::process;
 
-#[derive(Debug, PartialEq)]
-enum Message {
-    GIVE(Side),
-    REQUEST(Side),
-}
-
-impl Into<u8> for Message {
-    fn into(self) -> u8 {
-        match self {
-            Message::GIVE(Side::RIGHT) => 0,
-            Message::GIVE(Side::LEFT) => 1,
-            Message::REQUEST(Side::RIGHT) => 2,
-            Message::REQUEST(Side::LEFT) => 3,
-        }
-    }
-}
-
-impl From<u8> for Message {
-    fn from(value: u8) -> Self {
-        match value {
-            0 => Message::GIVE(Side::RIGHT),
-            1 => Message::GIVE(Side::LEFT),
-            2 => Message::REQUEST(Side::RIGHT),
-            3 => Message::REQUEST(Side::LEFT),
-            _ => panic!("Invalid <u8> value passed as a message => {}", value)
+fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
         }
-    }
-}
-
-
-#[derive(Debug)]
-struct Philosopher {
-    left_fork: ForkState,
-    right_fork: ForkState,
-    left_fork_request: bool,
-    right_fork_request: bool,
-    left_neighbour: i32,
-    right_neighbour: i32,
-}
-
-impl Philosopher {
-    fn new(size: i32, rank: i32) -> Self {
-        if rank == 0 {
-            Self {
-                left_fork: ForkState::DIRTY,
-                right_fork: ForkState::DIRTY,
-                left_fork_request: false,
-                right_fork_request: false,
-                left_neighbour: 1,
-                right_neighbour: size - 1,
-            }
-        } else if rank == size - 1 {
-            Self {
-                left_fork: ForkState::MISSING,
-                right_fork: ForkState::MISSING,
-                left_fork_request: false,
-                right_fork_request: false,
-                left_neighbour: 0,
-                right_neighbour: size - 2,
-            }
-        } else {
-            Self {
-                left_fork: ForkState::DIRTY,
-                right_fork: ForkState::MISSING,
-                left_fork_request: false,
-                right_fork_request: false,
-                left_neighbour: rank + 1,
-                right_neighbour: rank - 1,
+    };
+
+    if let Some(size) = config.model_check {
+        let graph = config.graph(size).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(2);
+        });
+        match model_check::model_check(config.strategy, config.acquisition, &graph) {
+            Ok(report) => println!(
+                "{} philosophers: {} states, {} transitions, no violations found",
+                size, report.states, report.transitions
+            ),
+            Err(counterexample) => {
+                print!("{}", counterexample);
+                process::exit(1);
             }
         }
+        return;
     }
 
-    fn eat(&mut self) {
-        self.left_fork = ForkState::DIRTY;
-        self.right_fork = ForkState::DIRTY;
-    }
-
-    fn check_forks_missing(&self) -> bool {
-        self.left_fork == ForkState::MISSING || self.right_fork == ForkState::MISSING
+    if let Some(schedule) = config.simulate {
+        simulate(schedule, &config);
+        return;
     }
 
-    fn received_fork(&mut self, msg_type: &Message, sender: i32, world: &SimpleCommunicator, indent: &String) -> Option<Side> {
-        match msg_type {
-            Message::GIVE(Side::LEFT) => {
-                println!("{}[{}] received left fork from [{}]!", indent, world.rank(), sender);
-                self.left_fork = ForkState::CLEAN;
-                Some(Side::LEFT)
-            } 
-            Message::GIVE(Side::RIGHT) => {
-                println!("{}[{}] received right fork from [{}]!", indent, world.rank(), sender);
-                self.right_fork = ForkState::CLEAN;
-                Some(Side::RIGHT)
-            }
-            _ => panic!("Invalid message received type => {:?}", msg_type)
+    if let Some(size) = config.threads {
+        if let Err(err) = runner::run_threads(size, &config).and_then(|ranks| stats::report(&ranks, &config)) {
+            eprintln!("aborting => {}", err);
+            process::exit(err.exit_code());
         }
+        return;
     }
 
-    fn respond_to_msg_request(&mut self, msg_type: &Message, sender: i32, world: &SimpleCommunicator, indent: &String) {
-        match msg_type {
-            Message::REQUEST(Side::LEFT) => {
-                if self.right_fork == ForkState::DIRTY {
-                    println!("{}[{}] giving right fork to [{}]!", indent, world.rank(), sender);
-                    world.process_at_rank(sender).send::<u8>(&Message::GIVE(Side::LEFT).into());
-                    self.right_fork = ForkState::MISSING;
-                    self.right_fork_request = false;
-                } else {
-                    self.right_fork_request = true
-                }
-            }
-            Message::REQUEST(Side::RIGHT) => {
-                if self.left_fork == ForkState::DIRTY {
-                    println!("{}[{}] giving left fork to {}!", indent, world.rank(), sender);
-                    world.process_at_rank(sender).send::<u8>(&Message::GIVE(Side::RIGHT).into());
-                    self.left_fork = ForkState::MISSING;
-                    self.left_fork_request = false;
-                } else {
-                    self.left_fork_request = true;
-                }
-            }
-            _ => panic!("Invalid message request type => {:?}", msg_type)
-        }
-    }
+    run_mpi(&config);
+}
 
-    fn respond_to_existing_requests(&mut self, world: &SimpleCommunicator, indent: &String) {
-        if self.left_fork_request {
-            println!("{}[{}] sending left fork to [{}]!", indent, world.rank(), self.left_neighbour);
-            world.process_at_rank(self.left_neighbour).send::<u8>(&Message::GIVE(Side::RIGHT).into());
-            self.left_fork = ForkState::MISSING;
-            self.left_fork_request = false;
-        }
-        if self.right_fork_request {
-            println!("{}[{}] sending right fork to [{}]!", indent, world.rank(), self.right_neighbour);
-            world.process_at_rank(self.right_neighbour).send::<u8>(&Message::GIVE(Side::LEFT).into());
-            self.right_fork = ForkState::MISSING;
-            self.right_fork_request = false;
-        }
+#[cfg(not(feature = "mpi"))]
+fn run_mpi(_config: &Config) {
+    eprintln!("this lab1 was built without MPI => run it with --threads N, --simulate or --model-check");
+    process::exit(2);
+}
+
+#[cfg(feature = "mpi")]
+fn run_mpi(config: &Config) {
+    // The transport receives on a thread of its own while `dine` sends.
+    let (universe, threading) = mpi::initialize_with_threading(Threading::Multiple).unwrap();
+    if threading != Threading::Multiple {
+        eprintln!("MPI must allow calls from several threads at once => it only offers {:?}", threading);
+        process::exit(2);
     }
+    let world = universe.world();
 
-    fn request_fork(&self, world: &SimpleCommunicator, indent: &String) -> Side {
-        if self.left_fork == ForkState::MISSING {
-            world.process_at_rank(self.left_neighbour).send::<u8>(&Message::REQUEST(Side::LEFT).into());
-            println!("{}[{}] requested left fork from [{}]!", indent, world.rank(), self.left_neighbour);
-            Side::LEFT
-        } else if self.right_fork == ForkState::MISSING {
-            world.process_at_rank(self.right_neighbour).send::<u8>(&Message::REQUEST(Side::RIGHT).into());
-            println!("{}[{}] requested right fork from [{}]!", indent, world.rank(), self.right_neighbour);
-            Side::RIGHT
-        } 
-        else {
-            panic!("Should be unreachable!");
-        }
+    // A rank that gives up would leave its neighbours blocked in `receive` forever, so any error
+    // takes the whole communicator down with it.
+    if let Err(err) = run(&world, config) {
+        eprintln!("[{}] aborting => {}", world.rank(), err);
+        world.abort(err.exit_code());
     }
 }
-    
-
 
-fn main() {
-    let universe = mpi::initialize().unwrap();
-    let world = universe.world();
+#[cfg(feature = "mpi")]
+fn run(world: &SimpleCommunicator, config: &Config) -> Result<(), PhilosopherError> {
     let size = world.size();
     let rank = world.rank();
 
-    let indent = "      ".repeat(rank.try_into().unwrap());
-
     if size < 2 {
-        panic!("");
+        return Err(PhilosopherError::TooFewPhilosophers(size));
     }
+    let graph = config.graph(size).map_err(PhilosopherError::Topology)?;
+
+    // Every rank works out its forks on its own, from its own reading of the options and files,
+    // so they compare notes before anyone asks for a fork.
+    let philosopher = runner::seat(&graph, rank, config);
+    let held = philosopher.held_forks(&graph);
+    let mut gathered = vec![false; held.len() * size as usize];
+    world.all_gather_into(&held[..], &mut gathered[..]);
+    graph.check_initial_forks(&gathered)?;
+
+    // Rank 0 picks the seed so that a run without --seed can still be replayed from its output.
+    let mut seed = config.seed.unwrap_or_else(rand::random);
+    world.process_at_rank(0).broadcast_into(&mut seed);
+    if rank == 0 {
+        eprintln!("[0] running with --seed {}", seed);
+    }
+    let sink = match config.log {
+        LogFormat::Tui => dashboard::mpi_sink(world, &graph),
+        _ => runner::create_sink(config, rank)?,
+    };
+
+    // Line the ranks up so that event timestamps from different ranks are roughly comparable.
+    world.barrier();
+    let transport = FaultyTransport::new(MpiTransport::new(world), config.faults.clone(), fault::seed_for(seed, size, rank));
+    let counters = runner::dine(&transport, &graph, philosopher, seed, sink, config)?.counters();
+    // Whatever a fault still holds back goes out before the ranks meet again.
+    drop(transport);
+
+    // Our counters are final once we have left the table, and rank 0 reports them for everyone.
+    let root = world.process_at_rank(0);
+    if rank == 0 {
+        let mut gathered = vec![0u64; Stats::COUNTERS * size as usize];
+        root.gather_into_root(&counters[..], &mut gathered[..]);
+        let ranks: Vec<Stats> = gathered.chunks(Stats::COUNTERS).map(Stats::from_counters).collect();
+        stats::report(&ranks, config)?;
+    } else {
+        root.gather_into(&counters[..]);
+    }
+    Ok(())
+}
 
-    let mut philosopher = Philosopher::new(size, rank);
-
-    loop {
-        let thinking_time = rand::rng().random_range(2..=5);
-        println!("{}[{}] is thinking!", indent, rank);
-        for _ in 0..thinking_time {
-            if let Some(_) = world.any_process().immediate_probe() {
-                let (msg, status)  = world.any_process().receive::<u8>();
-
-                let msg_type = Message::from(msg);
-                let sender = status.source_rank();
-
-                match msg_type {
-                    Message::GIVE(_) => {
-                        philosopher.received_fork(&msg_type, sender, &world, &indent);
-                    }
-                    Message::REQUEST(_) => {
-                        philosopher.respond_to_msg_request(&msg_type, sender, &world, &indent);
-                    }
-                }
-            
-            }
-            thread::sleep(time::Duration::from_secs(1));
-        } 
-        println!("{}[{}] finished thinking!", indent, rank);
-
-        while philosopher.check_forks_missing() {   
-
-            let requested_fork = philosopher.request_fork(&world, &indent);
-            let mut received = Option::None;
-
-            while received.take() != Some(requested_fork) {
-                let (msg, status) = world.any_process().receive::<u8>();
-
-                let msg_type = Message::from(msg);
-                let sender = status.source_rank();
-
-                match msg_type {
-                    Message::GIVE(_) => {
-                        received = philosopher.received_fork(&msg_type, sender, &world, &indent);
-                    }
-                    Message::REQUEST(_) => {
-                        philosopher.respond_to_msg_request(&msg_type, sender, &world, &indent);
-                    }
-                }
+fn simulate(schedule: sim::Schedule, config: &Config) {
+    let seed = config.seed.unwrap_or_else(rand::random);
+    eprintln!("[0] running with --seed {}", seed);
+
+    let result = (0..config.ranks).map(|rank| runner::create_sink(config, rank)).collect::<Result<Vec<_>, _>>()
+        .map_err(sim::SimulationError::from)
+        .and_then(|sinks| sim::simulate(config, config.ranks, seed, schedule, sinks));
+    match result {
+        Ok(report) => {
+            eprintln!("simulated {} meals in {:?} of virtual time, {} steps", report.meals, report.time, report.steps);
+            if let Err(err) = stats::report(&report.stats, config) {
+                eprintln!("{}", err);
+                process::exit(err.exit_code());
             }
         }
-
-        println!("{}Philosopher {} is eating!", indent, rank);
-        thread::sleep(time::Duration::from_secs(2));
-        philosopher.eat();
-
-        philosopher.respond_to_existing_requests(&world, &indent);
+        Err(err) => {
+            eprintln!("simulation failed => {}", err);
+            eprintln!("rerun it with the same options and --simulate {} --seed {}", schedule, seed);
+            process::exit(err.exit_code());
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,481 @@
+use crate::{
+    clock::{ Clock, Stamp }, error::PhilosopherError, event::{ EventLog, NullSink, Timebase }, strategy::{ self, Acquisition, DiningStrategy, StrategyKind },
+    topology::Graph, transport::Transport, wire::Envelope, ForkState, Message, Phase, Philosopher,
+};
+use std::{ cell::RefCell, collections::{ hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque }, fmt, time::Instant };
+
+pub const MIN_PHILOSOPHERS: i32 = 2;
+pub const MAX_PHILOSOPHERS: i32 = 5;
+
+/// One move of the whole system: a local step of a philosopher or the delivery of the oldest
+/// message between two ranks.
+#[derive(Debug, Clone)]
+enum Step {
+    /// With the neighbours the meal needs, when the strategy lets it choose.
+    Hungry(i32, Option<Vec<i32>>),
+    Eat(i32),
+    Finish(i32),
+    Deliver { from: i32, to: i32, message: Message },
+}
+
+/// Who takes a step, for fairness: a rank taking a local step, or the channel a message is delivered on.
+type Actor = (i32, i32);
+
+impl Step {
+    fn actor(&self) -> Actor {
+        match *self {
+            Step::Hungry(rank, _) | Step::Eat(rank) | Step::Finish(rank) => (rank, rank),
+            Step::Deliver { from, to, .. } => (from, to),
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Hungry(rank, None) => write!(f, "[{}] gets hungry", rank),
+            Step::Hungry(rank, Some(needs)) => write!(f, "[{}] gets hungry for what it shares with {:?}", rank, needs),
+            Step::Eat(rank) => write!(f, "[{}] starts eating", rank),
+            Step::Finish(rank) => write!(f, "[{}] finishes eating", rank),
+            Step::Deliver { from, to, message } => write!(f, "[{}] receives {:?} from [{}]", to, message, from),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Violation {
+    NeighboursEating { rank: i32, neighbour: i32 },
+    ForkOwners { fork: u32, owners: usize },
+    Deadlock,
+    Starvation { rank: i32 },
+    Protocol(PhilosopherError),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::NeighboursEating { rank, neighbour } => write!(f, "[{}] and its neighbour [{}] eat at the same time", rank, neighbour),
+            Violation::ForkOwners { fork, owners } => write!(f, "fork {} has {} owners, counting a GIVE in flight as one", fork, owners),
+            Violation::Deadlock => write!(f, "deadlock, nobody can move any more"),
+            Violation::Starvation { rank } => write!(f, "[{}] can stay hungry forever, even though every step that keeps being possible is taken", rank),
+            Violation::Protocol(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// A violation together with the shortest sequence of steps from the initial state that leads to it.
+#[derive(Debug)]
+pub struct Counterexample {
+    pub violation: Violation,
+    pub trace: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub states: usize,
+    pub transitions: usize,
+}
+
+/// Collects what a philosopher sends during one step.
+#[derive(Default)]
+struct Outbox {
+    sent: RefCell<Vec<(i32, Vec<u8>)>>,
+}
+
+impl Transport for Outbox {
+    fn send(&self, dest: i32, buf: &[u8]) {
+        self.sent.borrow_mut().push((dest, buf.to_vec()));
+    }
+
+    fn receive(&self) -> (Vec<u8>, i32) {
+        unreachable!("the model checker delivers messages itself")
+    }
+
+    fn receive_until(&self, _deadline: Instant) -> Option<(Vec<u8>, i32)> {
+        unreachable!("the model checker delivers messages itself")
+    }
+}
+
+struct State {
+    seats: Vec<Box<dyn DiningStrategy>>,
+    channels: BTreeMap<(i32, i32), VecDeque<Message>>,
+}
+
+impl Clone for State {
+    fn clone(&self) -> Self {
+        let seats = self.seats.iter().map(|strategy| strategy.clone_box()).collect();
+        Self { seats, channels: self.channels.clone() }
+    }
+}
+
+impl State {
+    fn phases(&self) -> Vec<Phase> {
+        self.seats.iter().map(|strategy| strategy.phase()).collect()
+    }
+
+    /// Identifies the state. Clocks, sequence numbers and meal counts are reset after every step,
+    /// so what is left is finite: fork states, request flags, strategy state and messages in flight.
+    fn key(&self) -> String {
+        format!("{:?} {:?}", self.seats, self.channels)
+    }
+
+    fn steps(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for (rank, strategy) in (0..).zip(&self.seats) {
+            match strategy.phase() {
+                Phase::Thinking => {
+                    let sessions = strategy.sessions();
+                    if sessions.is_empty() {
+                        steps.push(Step::Hungry(rank, None));
+                    }
+                    steps.extend(sessions.into_iter().map(|needs| Step::Hungry(rank, Some(needs))));
+                }
+                Phase::Hungry if strategy.can_eat() => steps.push(Step::Eat(rank)),
+                Phase::Hungry => {}
+                Phase::Eating => steps.push(Step::Finish(rank)),
+            }
+        }
+        // Like `dine`, a philosopher keeps reading messages while it eats.
+        for (&(from, to), queue) in &self.channels {
+            if let Some(message) = queue.front() {
+                steps.push(Step::Deliver { from, to, message: message.clone() });
+            }
+        }
+        steps
+    }
+
+    fn apply(&self, step: &Step) -> Result<State, PhilosopherError> {
+        let mut next = self.clone();
+        let outbox = Outbox::default();
+        let mut log = EventLog::with_timebase(0, Timebase::Virtual(Default::default()), Box::new(NullSink));
+
+        let rank = match step {
+            Step::Hungry(rank, needs) => {
+                if let Some(needs) = needs {
+                    next.seats[*rank as usize].choose_session(needs);
+                }
+                next.seats[*rank as usize].hungry(&outbox, &mut log)?;
+                *rank
+            }
+            Step::Eat(rank) => {
+                next.seats[*rank as usize].start_eating(&outbox, &mut log)?;
+                *rank
+            }
+            Step::Finish(rank) => {
+                next.seats[*rank as usize].finished_eating(&outbox, &mut log)?;
+                *rank
+            }
+            Step::Deliver { from, to, message } => {
+                let queue = next.channels.get_mut(&(*from, *to)).unwrap();
+                queue.pop_front();
+                // An empty queue has to look the same as one that was never used.
+                if queue.is_empty() {
+                    next.channels.remove(&(*from, *to));
+                }
+                let envelope = Envelope::new(0, message.clone(), Stamp { lamport: 0, vector: None }, 0);
+                next.seats[*to as usize].handle_message(&envelope, *from, &outbox, &mut log)?;
+                *to
+            }
+        };
+
+        next.post(rank, outbox);
+        Ok(next)
+    }
+
+    /// Queues what `rank` sent and forgets the parts of its state that only ever grow.
+    fn post(&mut self, rank: i32, outbox: Outbox) {
+        for (dest, buf) in outbox.sent.into_inner() {
+            let envelope = Envelope::try_from(&buf[..]).expect("we just encoded it");
+            self.channels.entry((rank, dest)).or_default().push_back(envelope.message);
+        }
+        self.seats[rank as usize].forget_history();
+    }
+
+    fn check(&self, graph: &Graph) -> Result<(), Violation> {
+        for (&(rank, neighbour), fork) in graph.edges().iter().zip(0..) {
+            let eating = |rank: i32, neighbour: i32| {
+                let strategy = &self.seats[rank as usize];
+                strategy.phase() == Phase::Eating && strategy.needs().contains(&neighbour)
+            };
+            if eating(rank, neighbour) && eating(neighbour, rank) {
+                return Err(Violation::NeighboursEating { rank, neighbour });
+            }
+
+            let held = |rank: i32, neighbour: i32| self.seats[rank as usize].philosopher().fork(neighbour) != ForkState::MISSING;
+            let in_flight = [(rank, neighbour), (neighbour, rank)].iter()
+                .filter_map(|pair| self.channels.get(pair))
+                .flatten()
+                .filter(|&message| *message == Message::GIVE(fork))
+                .count();
+            let owners = held(rank, neighbour) as usize + held(neighbour, rank) as usize + in_flight;
+            if owners != 1 {
+                return Err(Violation::ForkOwners { fork, owners });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Explores every interleaving of local steps and message deliveries of the philosophers of
+/// `graph` that think, get hungry and eat forever. Every reachable state is checked for safety
+/// and deadlock, and afterwards for every philosopher that no fair run keeps it hungry forever
+/// (see `check_progress`). The search is breadth first, so a counterexample is as short as
+/// possible. Drinking philosophers try every session they could pick whenever they get thirsty.
+pub fn model_check(kind: StrategyKind, acquisition: Acquisition, graph: &Graph) -> Result<Report, Counterexample> {
+    let size = graph.size();
+    let mut initial = State { seats: Vec::new(), channels: BTreeMap::new() };
+    for rank in 0..size {
+        let philosopher = Philosopher::new(graph, rank, Clock::new(size, rank, false));
+        initial.seats.push(strategy::create(kind, acquisition, philosopher, graph));
+    }
+    let held: Vec<_> = initial.seats.iter().flat_map(|strategy| strategy.philosopher().held_forks(graph)).collect();
+    graph.check_initial_forks(&held).map_err(|err| counterexample(Violation::Protocol(err), &[], 0))?;
+    for rank in 0..size {
+        let outbox = Outbox::default();
+        let mut log = EventLog::with_timebase(rank, Timebase::Virtual(Default::default()), Box::new(NullSink));
+        initial.seats[rank as usize].start(&outbox, &mut log).map_err(|err| counterexample(Violation::Protocol(err), &[], 0))?;
+        initial.post(rank, outbox);
+    }
+
+    // Per state: the step that first reached it and from where, its phases and who can move it where.
+    let mut parents: Vec<Option<(usize, Step)>> = vec![None];
+    let mut phases: Vec<Vec<Phase>> = vec![initial.phases()];
+    let mut successors: Vec<Vec<(Actor, usize)>> = Vec::new();
+    let mut seen = HashMap::from([(initial.key(), 0)]);
+    let mut queue = VecDeque::from([(0, initial)]);
+    let mut transitions = 0;
+
+    queue[0].1.check(graph).map_err(|violation| counterexample(violation, &parents, 0))?;
+
+    while let Some((index, state)) = queue.pop_front() {
+        let steps = state.steps();
+        if steps.is_empty() {
+            return Err(counterexample(Violation::Deadlock, &parents, index));
+        }
+
+        let mut next_indices = Vec::with_capacity(steps.len());
+        for step in steps {
+            transitions += 1;
+            let actor = step.actor();
+            let next = match state.apply(&step) {
+                Ok(next) => next,
+                Err(err) => {
+                    parents.push(Some((index, step)));
+                    return Err(counterexample(Violation::Protocol(err), &parents, parents.len() - 1));
+                }
+            };
+
+            let next_index = match seen.entry(next.key()) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let next_index = parents.len();
+                    entry.insert(next_index);
+                    parents.push(Some((index, step)));
+                    phases.push(next.phases());
+                    next.check(graph).map_err(|violation| counterexample(violation, &parents, next_index))?;
+                    queue.push_back((next_index, next));
+                    next_index
+                }
+            };
+            next_indices.push((actor, next_index));
+        }
+        successors.push(next_indices);
+    }
+
+    check_progress(size, &phases, &successors).map_err(|(violation, index)| counterexample(violation, &parents, index))?;
+    Ok(Report { states: parents.len(), transitions })
+}
+
+/// For every rank, looks for a fair run that keeps it hungry forever: one that cycles through
+/// states where it is hungry while every actor that can move infinitely often also does move
+/// infinitely often. Such a run lives in a strongly connected component of the hungry states. A
+/// component where some enabled actor cannot move without leaving it is shrunk to the states where
+/// that actor is not enabled, and searched again, until the actors enabled in it can all move
+/// inside it, or it is gone. States are numbered in breadth-first order, so the state reported is
+/// the one of the cycle that is closest to the start.
+fn check_progress(size: i32, phases: &[Vec<Phase>], successors: &[Vec<(Actor, usize)>]) -> Result<(), (Violation, usize)> {
+    let mut local = vec![usize::MAX; phases.len()];
+    for rank in 0..size {
+        let mut pending = vec![(0..phases.len()).filter(|&state| phases[state][rank as usize] == Phase::Hungry).collect::<Vec<_>>()];
+        let mut starving = None;
+        while let Some(states) = pending.pop() {
+            for component in components(&states, successors, &mut local) {
+                let inside: HashSet<usize> = component.iter().copied().collect();
+                let moves = |state: usize| successors[state].iter();
+                let enabled: HashSet<Actor> = component.iter().flat_map(|&state| moves(state).map(|&(actor, _)| actor)).collect();
+                let stays: HashSet<Actor> = component.iter()
+                    .flat_map(|&state| moves(state).filter(|(_, next)| inside.contains(next)).map(|&(actor, _)| actor))
+                    .collect();
+                // Without a step inside it, a single state is not a cycle.
+                if stays.is_empty() {
+                    continue;
+                }
+                let unfair: HashSet<Actor> = enabled.difference(&stays).copied().collect();
+                if unfair.is_empty() {
+                    let first = *component.iter().min().unwrap();
+                    starving = Some(starving.map_or(first, |state: usize| state.min(first)));
+                } else {
+                    pending.push(component.into_iter().filter(|&state| moves(state).all(|(actor, _)| !unfair.contains(actor))).collect());
+                }
+            }
+        }
+        if let Some(state) = starving {
+            return Err((Violation::Starvation { rank }, state));
+        }
+    }
+    Ok(())
+}
+
+/// The strongly connected components of `states` and the steps between them, by Tarjan's algorithm
+/// without recursion. `local` maps every state to `usize::MAX`, and is left that way.
+fn components(states: &[usize], successors: &[Vec<(Actor, usize)>], local: &mut [usize]) -> Vec<Vec<usize>> {
+    for (position, &state) in states.iter().enumerate() {
+        local[state] = position;
+    }
+    let mut index = vec![usize::MAX; states.len()];
+    let mut low = vec![0; states.len()];
+    let mut on_stack = vec![false; states.len()];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut counter = 0;
+
+    for root in 0..states.len() {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        index[root] = counter;
+        low[root] = counter;
+        counter += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        // Every node being visited, with how many of its steps it has looked at.
+        let mut calls = vec![(root, 0)];
+        while let Some(&(node, edge)) = calls.last() {
+            match successors[states[node]].get(edge) {
+                Some(&(_, next)) => {
+                    calls.last_mut().unwrap().1 += 1;
+                    let next = local[next];
+                    if next == usize::MAX {
+                        continue;
+                    }
+                    if index[next] == usize::MAX {
+                        index[next] = counter;
+                        low[next] = counter;
+                        counter += 1;
+                        stack.push(next);
+                        on_stack[next] = true;
+                        calls.push((next, 0));
+                    } else if on_stack[next] {
+                        low[node] = low[node].min(index[next]);
+                    }
+                }
+                None => {
+                    calls.pop();
+                    if let Some(&(parent, _)) = calls.last() {
+                        low[parent] = low[parent].min(low[node]);
+                    }
+                    if low[node] == index[node] {
+                        let mut component = Vec::new();
+                        loop {
+                            let member = stack.pop().unwrap();
+                            on_stack[member] = false;
+                            component.push(states[member]);
+                            if member == node {
+                                break;
+                            }
+                        }
+                        components.push(component);
+                    }
+                }
+            }
+        }
+    }
+
+    for &state in states {
+        local[state] = usize::MAX;
+    }
+    components
+}
+
+fn counterexample(violation: Violation, parents: &[Option<(usize, Step)>], mut index: usize) -> Counterexample {
+    let mut trace = Vec::new();
+    while let Some(Some((parent, step))) = parents.get(index) {
+        trace.push(step.to_string());
+        index = *parent;
+    }
+    trace.reverse();
+    Counterexample { violation, trace }
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}, reached in {} step(s):", self.violation, self.trace.len())?;
+        for (number, step) in self.trace.iter().enumerate() {
+            writeln!(f, "  {:>3}. {}", number + 1, step)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topology::Topology;
+
+    /// Every strategy but drinking, and Chandy–Misra with both ways of acquiring its forks.
+    const STRATEGIES: [(StrategyKind, Acquisition); 5] = [
+        (StrategyKind::ChandyMisra, Acquisition::Concurrent), (StrategyKind::ChandyMisra, Acquisition::Sequential),
+        (StrategyKind::ResourceOrder, Acquisition::Concurrent), (StrategyKind::Waiter, Acquisition::Concurrent),
+        (StrategyKind::TokenRing, Acquisition::Concurrent),
+    ];
+
+    #[test]
+    fn every_strategy_is_safe_and_live_up_to_four_philosophers() {
+        for (kind, acquisition) in STRATEGIES {
+            for size in MIN_PHILOSOPHERS..=4 {
+                if let Err(counterexample) = model_check(kind, acquisition, &Topology::Ring.graph(size).unwrap()) {
+                    panic!("{:?} ({:?}) with {} philosophers => {}", kind, acquisition, size, counterexample);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_strategy_is_safe_and_live_on_other_topologies() {
+        for (kind, acquisition) in STRATEGIES {
+            for (topology, size) in [(Topology::Star, 4), (Topology::Grid { rows: 1, cols: 4 }, 4)] {
+                if let Err(counterexample) = model_check(kind, acquisition, &topology.graph(size).unwrap()) {
+                    panic!("{:?} ({:?}) on a {} of {} => {}", kind, acquisition, topology, size, counterexample);
+                }
+            }
+        }
+    }
+
+    /// [0] is hungry in states 0 and 1, which [1] moves between, and eats in state 2.
+    #[test]
+    fn only_a_fair_cycle_of_hungry_states_is_starvation() {
+        use Phase::{ Eating, Hungry, Thinking };
+        let phases = vec![vec![Hungry, Thinking], vec![Hungry, Hungry], vec![Eating, Hungry]];
+        let starves = |successors: &[Vec<(Actor, usize)>]| match check_progress(2, &phases, successors) {
+            Err((Violation::Starvation { rank: 0 }, state)) => Some(state),
+            Err((violation, _)) => panic!("{}", violation),
+            Ok(()) => None,
+        };
+
+        // [0] can eat in state 1, and a fair run cannot keep it from doing so forever.
+        assert_eq!(starves(&[vec![((1, 1), 1)], vec![((1, 1), 0), ((0, 0), 2)], vec![((0, 0), 0)]]), None);
+        // [0] can still eat in state 1, but only if [1] happens to choose to let it.
+        assert_eq!(starves(&[vec![((1, 1), 1)], vec![((1, 1), 0), ((1, 1), 2)], vec![((0, 0), 0)]]), Some(0));
+    }
+
+    /// Bottles next to the forks, and a choice of them every time, multiply the states, so drinking
+    /// only gets the smaller tables.
+    #[test]
+    fn drinking_is_safe_and_live_on_small_tables() {
+        for (topology, size) in [(Topology::Ring, 2), (Topology::Star, 3)] {
+            if let Err(counterexample) = model_check(StrategyKind::Drinking, Acquisition::Concurrent, &topology.graph(size).unwrap()) {
+                panic!("drinking on a {} of {} => {}", topology, size, counterexample);
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{
+    clock::Clock, config::{ Config, LogFormat }, dashboard::{ self, Dashboard, ForwardSink }, error::PhilosopherError,
+    event::{ ConsoleSink, EventKind, EventLog, EventSink, JsonLinesSink, NullSink }, fault::{ self, FaultyTransport },
+    receive, receive_until, stats::Stats, strategy::{ self, DiningStrategy }, topology::Graph, transport::{ self, Transport }, Philosopher,
+};
+use rand::{ rngs::StdRng, SeedableRng };
+use std::{ io, process, sync::mpsc, thread, time };
+
+/// Runs the whole table inside this process, one thread per philosopher talking over channels,
+/// and returns the statistics of every rank.
+pub fn run_threads(size: i32, config: &Config) -> Result<Vec<Stats>, PhilosopherError> {
+    if size < 2 {
+        return Err(PhilosopherError::TooFewPhilosophers(size));
+    }
+    let graph = &config.graph(size).map_err(PhilosopherError::Topology)?;
+    let philosophers: Vec<_> = (0..size).map(|rank| seat(graph, rank, config)).collect();
+    graph.check_initial_forks(&philosophers.iter().flat_map(|philosopher| philosopher.held_forks(graph)).collect::<Vec<_>>())?;
+
+    let seed = config.seed.unwrap_or_else(rand::random);
+    eprintln!("[0] running with --seed {}", seed);
+
+    // With --log tui the philosophers stream their events to a dashboard on a thread of its own.
+    let (updates, monitor) = mpsc::channel();
+    let stats = thread::scope(|scope| {
+        if config.log == LogFormat::Tui {
+            scope.spawn(move || dashboard::watch(Dashboard::new(graph), monitor));
+        }
+        let handles: Vec<_> = (0..size).zip(philosophers).zip(transport::channels(size))
+            .map(|((rank, philosopher), transport)| {
+                let updates = updates.clone();
+                let transport = FaultyTransport::new(transport, config.faults.clone(), fault::seed_for(seed, size, rank));
+                let sink = move || match config.log {
+                    LogFormat::Tui => Ok(Box::new(ForwardSink::new(move |update| { let _ = updates.send(update); })) as Box<dyn EventSink>),
+                    _ => create_sink(config, rank),
+                };
+                scope.spawn(move || {
+                    // Like `MPI_Abort`, one failing philosopher ends the run for everyone, since the
+                    // others could otherwise wait for it forever.
+                    sink().and_then(|sink| dine(&transport, graph, philosopher, seed, sink, config)).unwrap_or_else(|err| {
+                        eprintln!("[{}] aborting => {}", rank, err);
+                        process::exit(err.exit_code());
+                    })
+                })
+            })
+            .collect();
+        // The dashboard stops once every philosopher has hung up, even if one never said goodbye.
+        drop(updates);
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    Ok(stats)
+}
+
+pub fn create_sink(config: &Config, rank: i32) -> Result<Box<dyn EventSink>, PhilosopherError> {
+    Ok(match (config.log, config.log_file(rank)) {
+        (LogFormat::Console, _) => Box::new(ConsoleSink::new(rank)),
+        // The dashboard needs the whole table, so `run_threads` and `dashboard::mpi_sink` set it up.
+        (LogFormat::Tui, _) => Box::new(NullSink),
+        (LogFormat::JsonLines, None) => Box::new(JsonLinesSink::new(io::stdout())),
+        (LogFormat::JsonLines, Some(path)) => match JsonLinesSink::create(&path) {
+            Ok(sink) => Box::new(sink),
+            Err(err) => return Err(PhilosopherError::EventLog { path, err }),
+        },
+    })
+}
+
+/// The philosopher `rank` starts out as, before the ranks compare their forks.
+pub fn seat(graph: &Graph, rank: i32, config: &Config) -> Philosopher {
+    Philosopher::new(graph, rank, Clock::new(graph.size(), rank, config.vector_clock))
+}
+
+/// Thinks, eats and finally leaves the table, and returns what `rank` did on the way.
+pub fn dine(transport: &dyn Transport, graph: &Graph, philosopher: Philosopher, seed: u64, sink: Box<dyn EventSink>, config: &Config) -> Result<Stats, PhilosopherError> {
+    let rank = philosopher.rank;
+    let mut strategy = strategy::create(config.strategy, config.acquisition, philosopher, graph);
+    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(rank as u64));
+
+    let start = time::Instant::now();
+    let mut log = EventLog::new(rank, start, sink);
+    strategy.start(transport, &mut log)?;
+
+    loop {
+        if config.should_stop(strategy.meals(), start.elapsed()) {
+            break;
+        }
+
+        let thinking_deadline = time::Instant::now() + config.think_time(rank).sample(&mut rng);
+        log.emit(&strategy.philosopher().clock, EventKind::ThinkStart);
+        handle_until(strategy.as_mut(), transport, thinking_deadline, &mut log)?;
+        log.emit(&strategy.philosopher().clock, EventKind::ThinkEnd);
+
+        if config.should_stop(strategy.meals(), start.elapsed()) {
+            break;
+        }
+
+        let hungry_since = time::Instant::now();
+        strategy.pick_session(&mut rng);
+        strategy.hungry(transport, &mut log)?;
+        while !strategy.can_eat() {
+            let (envelope, sender) = receive(transport)?;
+            strategy.handle_message(&envelope, sender, transport, &mut log)?;
+        }
+
+        // Neighbours still hear from us during the meal, they just do not get our forks.
+        log.emit(&strategy.philosopher().clock, EventKind::EatStart);
+        strategy.philosopher_mut().ate_after(hungry_since.elapsed());
+        strategy.start_eating(transport, &mut log)?;
+        let eating_deadline = time::Instant::now() + config.eat_time(rank).sample(&mut rng);
+        handle_until(strategy.as_mut(), transport, eating_deadline, &mut log)?;
+        log.emit(&strategy.philosopher().clock, EventKind::EatEnd);
+        strategy.finished_eating(transport, &mut log)?;
+    }
+
+    // Stop asking for forks, but keep handing them out until every other rank has stopped too.
+    // Nobody sends a REQUEST after its DONE, so once all DONEs are in no one can block on us.
+    log.emit(&strategy.philosopher().clock, EventKind::Done);
+    strategy.done(transport, &mut log)?;
+
+    while !strategy.can_leave() {
+        let (envelope, sender) = receive(transport)?;
+        strategy.handle_message(&envelope, sender, transport, &mut log)?;
+    }
+
+    log.emit(&strategy.philosopher().clock, EventKind::Leave);
+    log.close();
+    Ok(strategy.philosopher().stats().clone())
+}
+
+/// Answers every message that arrives before `deadline`, as soon as it arrives.
+fn handle_until(strategy: &mut dyn DiningStrategy, transport: &dyn Transport, deadline: time::Instant, log: &mut EventLog) -> Result<(), PhilosopherError> {
+    while let Some((envelope, sender)) = receive_until(transport, deadline)? {
+        strategy.handle_message(&envelope, sender, transport, log)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ config::Timing, strategy::StrategyKind };
+    use std::{ fs, time::Duration };
+
+    /// Runs a whole table on threads and checks from the event logs that everyone ate and left.
+    fn dine_on_threads(strategy: StrategyKind, size: i32) {
+        let dir = std::env::temp_dir().join(format!("lab1-{:?}-{}-{}", strategy, size, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            meals: Some(5),
+            seed: Some(7),
+            think: Timing::Uniform(Duration::ZERO, Duration::from_millis(1)),
+            eat: Timing::Fixed(Duration::from_micros(100)),
+            log: LogFormat::JsonLines,
+            log_file: Some(dir.join("{rank}.jsonl").to_string_lossy().into_owned()),
+            strategy,
+            ..Config::default()
+        };
+
+        let stats = run_threads(size, &config).unwrap();
+        let total = Stats::total(&stats);
+        assert_eq!(total.meals, 5 * size as u64, "{:?}", strategy);
+        assert_eq!(total.forks_sent, total.forks_received, "{:?} lost a fork on the way", strategy);
+
+        for rank in 0..size {
+            let events = fs::read_to_string(config.log_file(rank).unwrap()).unwrap();
+            let meals = events.lines().filter(|line| line.contains("\"kind\":\"eat_start\"")).count();
+            assert_eq!(meals, 5, "[{}] ate {} times with {:?}", rank, meals, strategy);
+            assert!(events.lines().last().unwrap().contains("\"kind\":\"leave\""), "[{}] never left with {:?}", rank, strategy);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn every_strategy_runs_to_completion_on_threads() {
+        for strategy in [StrategyKind::ChandyMisra, StrategyKind::ResourceOrder, StrategyKind::Waiter, StrategyKind::TokenRing, StrategyKind::Drinking] {
+            for size in [2, 3, 5] {
+                dine_on_threads(strategy, size);
+            }
+        }
+    }
+
+    #[test]
+    fn chandy_misra_rides_out_delayed_messages() {
+        // Delays keep the order between two ranks, which is all Chandy–Misra relies on.
+        let config = Config {
+            meals: Some(5),
+            seed: Some(3),
+            think: Timing::Uniform(Duration::ZERO, Duration::from_millis(1)),
+            eat: Timing::Fixed(Duration::from_micros(100)),
+            log: LogFormat::JsonLines,
+            log_file: Some("/dev/null".to_string()),
+            faults: "delay:0.5:exp:1ms".parse().unwrap(),
+            ..Config::default()
+        };
+        let total = Stats::total(&run_threads(5, &config).unwrap());
+        assert_eq!(total.meals, 25);
+        assert_eq!(total.forks_sent, total.forks_received);
+    }
+}
@@ -0,0 +1,600 @@
+use crate::{
+    clock::Clock, config::Config, error::PhilosopherError, event::{ EventKind, EventLog, EventSink, Timebase }, fault::{ self, FaultyTransport },
+    receive, stats::Stats, strategy::{ self, DiningStrategy }, transport::Transport, Philosopher,
+};
+use rand::{ rngs::StdRng, Rng, SeedableRng };
+use std::{ cell::{ Cell, RefCell }, collections::{ BTreeMap, VecDeque }, fmt, rc::Rc, str::FromStr, time::{ Duration, Instant } };
+
+/// Gives up on runs that keep exchanging messages without time ever moving on.
+const MAX_STEPS: u64 = 50_000_000;
+
+/// Which pending message, or which expiring think or eat phase, the simulator picks next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Uniformly among everything that could happen next.
+    Random,
+    /// Each rank in turn gets its oldest message delivered, with time moving on once per round.
+    RoundRobin,
+    /// Time moves on whenever it can, and otherwise the newest message overtakes older ones.
+    Adversarial,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Schedule::Random),
+            "round-robin" => Ok(Schedule::RoundRobin),
+            "adversarial" => Ok(Schedule::Adversarial),
+            _ => Err(format!("invalid schedule => {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Schedule::Random => write!(f, "random"),
+            Schedule::RoundRobin => write!(f, "round-robin"),
+            Schedule::Adversarial => write!(f, "adversarial"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SimulationError {
+    Philosopher(PhilosopherError),
+    NeighboursEating { time: Duration, rank: i32, neighbour: i32 },
+    Deadlock { time: Duration, waiting: Vec<i32> },
+    TooManySteps(u64),
+}
+
+impl SimulationError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SimulationError::Philosopher(err) => err.exit_code(),
+            _ => 7,
+        }
+    }
+}
+
+impl From<PhilosopherError> for SimulationError {
+    fn from(err: PhilosopherError) -> Self {
+        SimulationError::Philosopher(err)
+    }
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::Philosopher(err) => write!(f, "{}", err),
+            SimulationError::NeighboursEating { time, rank, neighbour } => write!(f, "[{}] and its neighbour [{}] were both eating at {:?}", rank, neighbour, time),
+            SimulationError::Deadlock { time, waiting } => write!(f, "deadlock at {:?}, nothing left to deliver while {:?} still wait", time, waiting),
+            SimulationError::TooManySteps(steps) => write!(f, "no end in sight after {} steps", steps),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SimulationError::Philosopher(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub meals: u32,
+    pub time: Duration,
+    pub steps: u64,
+    /// Per rank, with hungry waits in virtual time.
+    pub stats: Vec<Stats>,
+}
+
+/// Undelivered messages from one rank to another, each tagged with when it was sent.
+type Pending = VecDeque<(u64, Vec<u8>)>;
+
+/// Messages that were sent but not delivered yet. They are kept per ordered pair of ranks, so each
+/// pair stays FIFO like MPI whatever the scheduler picks.
+struct Network {
+    in_flight: BTreeMap<(i32, i32), Pending>,
+    inboxes: Vec<VecDeque<(i32, Vec<u8>)>>,
+    sent: u64,
+}
+
+struct SimTransport<'a> {
+    rank: i32,
+    network: &'a RefCell<Network>,
+}
+
+impl Transport for SimTransport<'_> {
+    fn send(&self, dest: i32, buf: &[u8]) {
+        let mut network = self.network.borrow_mut();
+        network.sent += 1;
+        let sent = network.sent;
+        network.in_flight.entry((self.rank, dest)).or_default().push_back((sent, buf.to_vec()));
+    }
+
+    fn receive(&self) -> (Vec<u8>, i32) {
+        let (sender, buf) = self.network.borrow_mut().inboxes[self.rank as usize].pop_front()
+            .expect("the simulator only lets a rank receive what it delivered");
+        (buf, sender)
+    }
+
+    fn receive_until(&self, _deadline: Instant) -> Option<(Vec<u8>, i32)> {
+        let (sender, buf) = self.network.borrow_mut().inboxes[self.rank as usize].pop_front()?;
+        Some((buf, sender))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Thinking(Duration),
+    Hungry,
+    Eating(Duration),
+    Done,
+    Left,
+}
+
+/// One philosopher of the simulated table, stepped through the same phases as `dine`.
+struct Seat<'a> {
+    rank: i32,
+    strategy: Box<dyn DiningStrategy>,
+    transport: FaultyTransport<SimTransport<'a>>,
+    log: EventLog,
+    rng: StdRng,
+    phase: Phase,
+    hungry_since: Duration,
+}
+
+impl Seat<'_> {
+    fn timer(&self) -> Option<Duration> {
+        match self.phase {
+            Phase::Thinking(until) | Phase::Eating(until) => Some(until),
+            _ => None,
+        }
+    }
+
+    /// Like `dine`, we answer messages during a meal too, and only stop once we have left.
+    fn accepts_messages(&self) -> bool {
+        self.phase != Phase::Left
+    }
+
+    fn think(&mut self, config: &Config, now: Duration) -> Result<(), PhilosopherError> {
+        if config.should_stop(self.strategy.meals(), now) {
+            return self.finish();
+        }
+        self.log.emit(&self.strategy.philosopher().clock, EventKind::ThinkStart);
+        self.phase = Phase::Thinking(now + config.think_time(self.rank).sample(&mut self.rng));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), PhilosopherError> {
+        self.log.emit(&self.strategy.philosopher().clock, EventKind::Done);
+        self.strategy.done(&self.transport, &mut self.log)?;
+        self.phase = Phase::Done;
+        Ok(())
+    }
+
+    fn expire(&mut self, config: &Config, now: Duration) -> Result<(), PhilosopherError> {
+        match self.phase {
+            Phase::Thinking(_) => {
+                self.log.emit(&self.strategy.philosopher().clock, EventKind::ThinkEnd);
+                if config.should_stop(self.strategy.meals(), now) {
+                    return self.finish();
+                }
+                self.hungry_since = now;
+                self.strategy.pick_session(&mut self.rng);
+                self.strategy.hungry(&self.transport, &mut self.log)?;
+                self.phase = Phase::Hungry;
+            }
+            Phase::Eating(_) => {
+                self.log.emit(&self.strategy.philosopher().clock, EventKind::EatEnd);
+                self.strategy.finished_eating(&self.transport, &mut self.log)?;
+                self.think(config, now)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self) -> Result<(), PhilosopherError> {
+        let (envelope, sender) = receive(&self.transport)?;
+        self.strategy.handle_message(&envelope, sender, &self.transport, &mut self.log)
+    }
+
+    /// Takes the steps that need no more than what has already happened.
+    fn advance(&mut self, config: &Config, now: Duration) -> Result<(), PhilosopherError> {
+        if self.phase == Phase::Hungry && self.strategy.can_eat() {
+            self.log.emit(&self.strategy.philosopher().clock, EventKind::EatStart);
+            self.strategy.philosopher_mut().ate_after(now - self.hungry_since);
+            self.strategy.start_eating(&self.transport, &mut self.log)?;
+            self.phase = Phase::Eating(now + config.eat_time(self.rank).sample(&mut self.rng));
+        }
+        if self.phase == Phase::Done && self.strategy.can_leave() {
+            self.log.emit(&self.strategy.philosopher().clock, EventKind::Leave);
+            self.phase = Phase::Left;
+        }
+        Ok(())
+    }
+}
+
+enum Action {
+    Deliver(i32, i32),
+    Expire(usize),
+}
+
+/// Runs a whole table in this thread on virtual time, so no one ever sleeps. The same config,
+/// schedule and seed always give the same run. Delay faults would take real time, so they are left
+/// out and the schedule alone decides when messages arrive.
+pub fn simulate(config: &Config, size: i32, seed: u64, schedule: Schedule, sinks: Vec<Box<dyn EventSink>>) -> Result<Report, SimulationError> {
+    if size < 2 {
+        return Err(PhilosopherError::TooFewPhilosophers(size).into());
+    }
+    let graph = config.graph(size).map_err(PhilosopherError::Topology)?;
+
+    let faults = config.faults.without_delays();
+    let now = Rc::new(Cell::new(Duration::ZERO));
+    let network = RefCell::new(Network { in_flight: BTreeMap::new(), inboxes: vec![VecDeque::new(); size as usize], sent: 0 });
+    let mut seats: Vec<Seat> = (0..size).zip(sinks)
+        .map(|(rank, sink)| Seat {
+            rank,
+            strategy: strategy::create(config.strategy, config.acquisition, Philosopher::new(&graph, rank, Clock::new(size, rank, config.vector_clock)), &graph),
+            transport: FaultyTransport::new(SimTransport { rank, network: &network }, faults.clone(), fault::seed_for(seed, size, rank)),
+            log: EventLog::with_timebase(rank, Timebase::Virtual(now.clone()), sink),
+            rng: StdRng::seed_from_u64(seed.wrapping_add(rank as u64)),
+            phase: Phase::Thinking(Duration::ZERO),
+            hungry_since: Duration::ZERO,
+        })
+        .collect();
+    graph.check_initial_forks(&seats.iter().flat_map(|seat| seat.strategy.philosopher().held_forks(&graph)).collect::<Vec<_>>())?;
+    // The ranks draw from `seed + rank`, so the scheduler takes a stream none of them uses.
+    let mut scheduler = StdRng::seed_from_u64(seed.wrapping_sub(1));
+    let mut turn = 0;
+
+    for seat in &mut seats {
+        seat.strategy.start(&seat.transport, &mut seat.log)?;
+        seat.think(config, now.get())?;
+        seat.transport.flush();
+    }
+
+    let mut steps = 0;
+    while !seats.iter().all(|seat| seat.phase == Phase::Left) {
+        steps += 1;
+        if steps > MAX_STEPS {
+            return Err(SimulationError::TooManySteps(MAX_STEPS));
+        }
+
+        // Heads of the pairs whose receiver is listening, as (sent, from, to).
+        let deliverable: Vec<(u64, i32, i32)> = network.borrow().in_flight.iter()
+            .filter(|&(&(_, to), queue)| !queue.is_empty() && seats[to as usize].accepts_messages())
+            .map(|(&(from, to), queue)| (queue[0].0, from, to))
+            .collect();
+        let expiring = seats.iter().enumerate()
+            .filter_map(|(rank, seat)| seat.timer().map(|until| (until, rank)))
+            .min()
+            .map(|(_, rank)| rank);
+
+        let action = match schedule {
+            Schedule::Random => {
+                let choices = deliverable.len() + expiring.is_some() as usize;
+                if choices == 0 {
+                    None
+                } else {
+                    match deliverable.get(scheduler.random_range(0..choices)) {
+                        Some(&(_, from, to)) => Some(Action::Deliver(from, to)),
+                        None => expiring.map(Action::Expire),
+                    }
+                }
+            }
+            Schedule::RoundRobin => {
+                // Slots 0..size are the ranks, slot `size` lets time move on.
+                let slots = size as usize + 1;
+                let start = turn;
+                (0..slots).map(|offset| (start + offset) % slots).find_map(|slot| {
+                    let action = if slot == size as usize {
+                        expiring.map(Action::Expire)
+                    } else {
+                        deliverable.iter().filter(|&&(_, _, to)| to as usize == slot).min()
+                            .map(|&(_, from, to)| Action::Deliver(from, to))
+                    };
+                    turn = slot + 1;
+                    action
+                })
+            }
+            Schedule::Adversarial => expiring.map(Action::Expire)
+                .or_else(|| deliverable.iter().max().map(|&(_, from, to)| Action::Deliver(from, to))),
+        };
+
+        let rank = match action {
+            Some(Action::Deliver(from, to)) => {
+                let mut net = network.borrow_mut();
+                let (_, buf) = net.in_flight.get_mut(&(from, to)).unwrap().pop_front().unwrap();
+                net.inboxes[to as usize].push_back((from, buf));
+                drop(net);
+                seats[to as usize].deliver()?;
+                to as usize
+            }
+            Some(Action::Expire(rank)) => {
+                now.set(seats[rank].timer().unwrap());
+                seats[rank].expire(config, now.get())?;
+                rank
+            }
+            None => {
+                let waiting = seats.iter().filter(|seat| seat.phase != Phase::Left).map(|seat| seat.rank).collect();
+                return Err(SimulationError::Deadlock { time: now.get(), waiting });
+            }
+        };
+        seats[rank].advance(config, now.get())?;
+        // A step ends with the seat waiting for messages again, which lets out what faults held back.
+        seats[rank].transport.flush();
+
+        if matches!(seats[rank].phase, Phase::Eating(_)) {
+            // Drinking neighbours only clash over a bottle both of their sessions need.
+            let rank = rank as i32;
+            for neighbour in seats[rank as usize].strategy.needs() {
+                let seat = &seats[neighbour as usize];
+                if matches!(seat.phase, Phase::Eating(_)) && seat.strategy.needs().contains(&rank) {
+                    return Err(SimulationError::NeighboursEating { time: now.get(), rank, neighbour });
+                }
+            }
+        }
+    }
+
+    let meals = seats.iter().map(|seat| seat.strategy.meals()).sum();
+    let stats = seats.iter().map(|seat| seat.strategy.philosopher().stats().clone()).collect();
+    Ok(Report { meals, time: now.get(), steps, stats })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ config::Timing, event::{ Event, EventKind, NullSink }, fault::{ self, Faults }, strategy::{ Acquisition, StrategyKind }, topology::Topology, ForkId };
+    use std::collections::BTreeSet;
+
+    /// The parts of an event that a replay has to reproduce and tests follow a rank through a run by.
+    type Moment = (i32, u128, EventKind, Option<ForkId>, Option<i32>, Option<u128>);
+
+    /// Keeps every event as a `Moment`, so that whole runs can be compared.
+    struct Timeline(Rc<RefCell<Vec<Moment>>>);
+
+    impl EventSink for Timeline {
+        fn record(&mut self, event: &Event) {
+            self.0.borrow_mut().push((event.rank, event.time_us, event.kind, event.fork, event.peer, event.waited_us));
+        }
+    }
+
+    fn config(strategy: StrategyKind, acquisition: Acquisition, topology: Topology) -> Config {
+        Config {
+            meals: Some(20),
+            think: Timing::Exponential(Duration::from_secs(1)),
+            eat: Timing::Uniform(Duration::from_millis(100), Duration::from_secs(1)),
+            strategy,
+            acquisition,
+            topology,
+            ..Config::default()
+        }
+    }
+
+    fn run(strategy: StrategyKind, acquisition: Acquisition, schedule: Schedule, topology: Topology, size: i32, seed: u64) -> Report {
+        run_into(strategy, acquisition, schedule, topology, size, seed, || Box::new(NullSink))
+    }
+
+    fn timeline(strategy: StrategyKind, acquisition: Acquisition, schedule: Schedule, topology: Topology, size: i32, seed: u64) -> Vec<Moment> {
+        let moments = Rc::new(RefCell::new(Vec::new()));
+        run_into(strategy, acquisition, schedule, topology, size, seed, || Box::new(Timeline(moments.clone())));
+        let moments = moments.borrow().clone();
+        moments
+    }
+
+    fn run_into(
+        strategy: StrategyKind, acquisition: Acquisition, schedule: Schedule, topology: Topology, size: i32, seed: u64, sink: impl Fn() -> Box<dyn EventSink>,
+    ) -> Report {
+        let config = config(strategy, acquisition, topology.clone());
+        let sinks = (0..size).map(|_| sink()).collect();
+        simulate(&config, size, seed, schedule, sinks).unwrap_or_else(|err| {
+            panic!("{:?} ({:?}) under {} on a {} of {} ranks with seed {} => {}", strategy, acquisition, schedule, topology, size, seed, err)
+        })
+    }
+
+    #[test]
+    fn every_strategy_survives_every_schedule() {
+        let strategies = [
+            (StrategyKind::ChandyMisra, Acquisition::Concurrent), (StrategyKind::ChandyMisra, Acquisition::Sequential),
+            (StrategyKind::ResourceOrder, Acquisition::Concurrent), (StrategyKind::Waiter, Acquisition::Concurrent),
+            (StrategyKind::TokenRing, Acquisition::Concurrent), (StrategyKind::Drinking, Acquisition::Concurrent),
+        ];
+        for (strategy, acquisition) in strategies {
+            for schedule in [Schedule::Random, Schedule::RoundRobin, Schedule::Adversarial] {
+                for size in 2..=6 {
+                    for seed in 0..5 {
+                        let report = run(strategy, acquisition, schedule, Topology::Ring, size, seed);
+                        assert_eq!(report.meals, 20 * size as u32);
+                        let total = Stats::total(&report.stats);
+                        assert_eq!(total.meals, report.meals as u64);
+                        assert_eq!(total.forks_sent, total.forks_received, "{:?} left a fork in flight", strategy);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_strategy_survives_other_topologies() {
+        let topologies = [(Topology::Complete, 4), (Topology::Star, 5), (Topology::Grid { rows: 2, cols: 3 }, 6), (Topology::Grid { rows: 3, cols: 3 }, 9)];
+        for strategy in [StrategyKind::ChandyMisra, StrategyKind::ResourceOrder, StrategyKind::Waiter, StrategyKind::TokenRing, StrategyKind::Drinking] {
+            for schedule in [Schedule::Random, Schedule::RoundRobin, Schedule::Adversarial] {
+                for (topology, size) in &topologies {
+                    for seed in 0..3 {
+                        let report = run(strategy, Acquisition::Concurrent, schedule, topology.clone(), *size, seed);
+                        assert_eq!(report.meals, 20 * *size as u32);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_replays_the_same_run() {
+        let first = timeline(StrategyKind::ChandyMisra, Acquisition::Concurrent, Schedule::Random, Topology::Ring, 5, 42);
+        let second = timeline(StrategyKind::ChandyMisra, Acquisition::Concurrent, Schedule::Random, Topology::Ring, 5, 42);
+        let other = timeline(StrategyKind::ChandyMisra, Acquisition::Concurrent, Schedule::Random, Topology::Ring, 5, 43);
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn requests_that_arrive_during_a_meal_are_answered_as_it_ends() {
+        // The waiter never seats anyone next to a philosopher who eats, so only the others count.
+        let mut during_meals = 0;
+        for strategy in [StrategyKind::ChandyMisra, StrategyKind::ResourceOrder, StrategyKind::Waiter, StrategyKind::TokenRing] {
+            let events = timeline(strategy, Acquisition::Concurrent, Schedule::Random, Topology::Ring, 5, 11);
+
+            for rank in 0..5 {
+                let (mut eating, mut last_meal_end) = (false, 0);
+                let mut pending = BTreeMap::new();
+                let mut arrived_during_meal = BTreeSet::new();
+
+                for &(_, time, kind, _, peer, waited) in events.iter().filter(|event| event.0 == rank) {
+                    match kind {
+                        EventKind::EatStart => eating = true,
+                        EventKind::EatEnd => (eating, last_meal_end) = (false, time),
+                        EventKind::RequestReceived => {
+                            let peer = peer.unwrap();
+                            pending.entry(peer).or_insert(time);
+                            if eating {
+                                arrived_during_meal.insert(peer);
+                            }
+                        }
+                        EventKind::ForkGiven => {
+                            let peer = peer.unwrap();
+                            assert!(!eating, "{:?}: [{}] gave a fork to [{}] while eating at {}us", strategy, rank, peer, time);
+                            let since = pending.remove(&peer).expect("every fork is given on request");
+                            assert_eq!(waited, Some(time - since), "{:?}: [{}] timed the request of [{}] wrong", strategy, rank, peer);
+                            if arrived_during_meal.remove(&peer) {
+                                assert_eq!(time, last_meal_end, "{:?}: [{}] kept [{}] waiting after its meal", strategy, rank, peer);
+                                during_meals += 1;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        assert!(during_meals > 0, "no request ever arrived during a meal");
+    }
+
+    #[test]
+    fn drinking_neighbours_share_the_table_when_their_bottles_differ() {
+        let mut together = 0;
+        for seed in 0..3 {
+            let events = timeline(StrategyKind::Drinking, Acquisition::Concurrent, Schedule::Random, Topology::Ring, 5, seed);
+            let mut sessions = vec![Vec::new(); 5];
+            let mut since = BTreeMap::new();
+            for &(rank, time, kind, ..) in &events {
+                match kind {
+                    EventKind::EatStart => {
+                        since.insert(rank, time);
+                    }
+                    EventKind::EatEnd => sessions[rank as usize].push((since.remove(&rank).unwrap(), time)),
+                    _ => {}
+                }
+            }
+            // The simulation already fails when neighbours drink from the same bottle.
+            for rank in 0..5 {
+                for &(start, end) in &sessions[rank] {
+                    together += sessions[(rank + 1) % 5].iter().filter(|&&(other_start, other_end)| start < other_end && other_start < end).count();
+                }
+            }
+        }
+        assert!(together > 0, "neighbours never drank at the same time");
+    }
+
+    #[test]
+    fn concurrent_acquisition_keeps_hungry_philosophers_waiting_less() {
+        let hungry_us = |acquisition| {
+            let mut total = 0;
+            for seed in 0..5 {
+                let events = timeline(StrategyKind::ChandyMisra, acquisition, Schedule::Random, Topology::Ring, 5, seed);
+                let mut since = BTreeMap::new();
+                for &(rank, time, kind, ..) in &events {
+                    match kind {
+                        EventKind::ThinkEnd => {
+                            since.insert(rank, time);
+                        }
+                        EventKind::EatStart => total += time - since.remove(&rank).unwrap(),
+                        _ => {}
+                    }
+                }
+            }
+            total
+        };
+        let (concurrent, sequential) = (hungry_us(Acquisition::Concurrent), hungry_us(Acquisition::Sequential));
+        assert!(concurrent < sequential, "hungry for {}us asking for all forks at once, {}us one at a time", concurrent, sequential);
+    }
+
+    /// Chandy–Misra on a ring of five with every message that `faults` names hit by it.
+    fn run_with_faults(faults: &str, messages: &str, seed: u64) -> Result<Report, SimulationError> {
+        let mut config = config(StrategyKind::ChandyMisra, Acquisition::Concurrent, Topology::Ring);
+        config.faults = Faults { messages: fault::parse_messages(messages).unwrap(), ..faults.parse().unwrap() };
+        let sinks = (0..5).map(|_| Box::new(NullSink) as Box<dyn EventSink>).collect();
+        simulate(&config, 5, seed, Schedule::Random, sinks)
+    }
+
+    #[test]
+    fn a_dropped_message_leaves_the_whole_table_waiting_forever() {
+        // A lost fork or request keeps its hungry end waiting, and a lost goodbye keeps everyone
+        // from leaving, so sooner or later nobody moves on.
+        for message in ["give", "request", "done"] {
+            for seed in 0..3 {
+                match run_with_faults("drop:1", message, seed) {
+                    Err(SimulationError::Deadlock { waiting, .. }) => assert_eq!(waiting, [0, 1, 2, 3, 4], "dropping {} with seed {}", message, seed),
+                    other => panic!("dropping {} with seed {} => {:?}", message, seed, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_duplicated_give_hands_one_fork_to_both_of_its_ends() {
+        // The copy follows the request that takes the fork back, and `received_fork` cleans a fork we
+        // no longer hold without a word, so both ends believe they hold it.
+        let mut both_eating = 0;
+        for seed in 0..6 {
+            match run_with_faults("duplicate:1", "give", seed) {
+                Err(SimulationError::NeighboursEating { .. }) => both_eating += 1,
+                Err(SimulationError::Deadlock { .. }) => {}
+                other => panic!("duplicating gives with seed {} => {:?}", seed, other),
+            }
+        }
+        assert!(both_eating > 0, "neighbours never ate with the same fork");
+    }
+
+    #[test]
+    fn a_duplicated_done_lets_philosophers_leave_before_everyone_is_done() {
+        // Every goodbye is counted, so two from the same rank let the listener leave while a neighbour
+        // may still need its fork.
+        for seed in 0..3 {
+            match run_with_faults("duplicate:1", "done", seed) {
+                Err(SimulationError::Deadlock { waiting, .. }) => assert!(waiting.len() < 5, "nobody left early with seed {}", seed),
+                other => panic!("duplicating goodbyes with seed {} => {:?}", seed, other),
+            }
+        }
+    }
+
+    #[test]
+    fn reordered_messages_do_chandy_misra_no_harm() {
+        // The only messages one step sends to the same rank are a fork followed by the request to
+        // have it back. When the request overtakes the fork it is remembered like any request for a
+        // clean fork, and answered once the fork has been eaten with.
+        for seed in 0..5 {
+            let report = run_with_faults("reorder:1", "give,request,done", seed).unwrap();
+            assert_eq!(report.meals, 100);
+            let total = Stats::total(&report.stats);
+            assert_eq!(total.forks_sent, total.forks_received);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{ config::{ Config, StatsFormat }, error::PhilosopherError };
+use std::{ fmt::Write as _, fs, time::Duration };
+
+/// What one philosopher did during a run. Everything is a plain counter, so that rank 0 can
+/// collect them from every rank with a single gather of `COUNTERS` numbers each.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub meals: u64,
+    pub hungry_total_us: u64,
+    pub hungry_max_us: u64,
+    pub forks_sent: u64,
+    pub forks_received: u64,
+    pub requests_deferred: u64,
+    pub messages_sent: u64,
+}
+
+impl Stats {
+    pub const COUNTERS: usize = 7;
+
+    /// Counts a meal that we waited `hungry` for.
+    pub fn ate_after(&mut self, hungry: Duration) {
+        let hungry_us = hungry.as_micros() as u64;
+        self.meals += 1;
+        self.hungry_total_us += hungry_us;
+        self.hungry_max_us = self.hungry_max_us.max(hungry_us);
+    }
+
+    pub fn counters(&self) -> [u64; Self::COUNTERS] {
+        [self.meals, self.hungry_total_us, self.hungry_max_us, self.forks_sent, self.forks_received, self.requests_deferred, self.messages_sent]
+    }
+
+    /// The inverse of `counters`. Panics unless given exactly `COUNTERS` numbers.
+    pub fn from_counters(counters: &[u64]) -> Self {
+        let [meals, hungry_total_us, hungry_max_us, forks_sent, forks_received, requests_deferred, messages_sent] = counters.try_into().unwrap();
+        Self { meals, hungry_total_us, hungry_max_us, forks_sent, forks_received, requests_deferred, messages_sent }
+    }
+
+    /// Adds up the counters of several ranks, keeping the longest wait.
+    pub fn total<'a>(all: impl IntoIterator<Item = &'a Stats>) -> Self {
+        let mut total = Stats::default();
+        for stats in all {
+            total.meals += stats.meals;
+            total.hungry_total_us += stats.hungry_total_us;
+            total.hungry_max_us = total.hungry_max_us.max(stats.hungry_max_us);
+            total.forks_sent += stats.forks_sent;
+            total.forks_received += stats.forks_received;
+            total.requests_deferred += stats.requests_deferred;
+            total.messages_sent += stats.messages_sent;
+        }
+        total
+    }
+
+    pub fn hungry_mean_us(&self) -> u64 {
+        self.hungry_total_us.checked_div(self.meals).unwrap_or(0)
+    }
+
+    pub fn messages_per_meal(&self) -> f64 {
+        if self.meals == 0 { 0.0 } else { self.messages_sent as f64 / self.meals as f64 }
+    }
+}
+
+fn ms(us: u64) -> String {
+    format!("{:.1}ms", us as f64 / 1000.0)
+}
+
+/// One line per rank, in rank order, and one for the whole table.
+pub fn table(ranks: &[Stats]) -> String {
+    let mut out = String::new();
+    let header = ("rank", "meals", "hungry avg", "hungry max", "forks sent", "forks recv", "deferred", "msgs/meal");
+    writeln!(out, "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10} {:>9} {:>9}", header.0, header.1, header.2, header.3, header.4, header.5, header.6, header.7).unwrap();
+
+    let total = Stats::total(ranks);
+    let rows = ranks.iter().enumerate().map(|(rank, stats)| (rank.to_string(), stats)).chain([("all".to_string(), &total)]);
+    for (rank, stats) in rows {
+        writeln!(
+            out,
+            "{:>6} {:>7} {:>10} {:>10} {:>10} {:>10} {:>9} {:>9.1}",
+            rank, stats.meals, ms(stats.hungry_mean_us()), ms(stats.hungry_max_us),
+            stats.forks_sent, stats.forks_received, stats.requests_deferred, stats.messages_per_meal()
+        ).unwrap();
+    }
+    out
+}
+
+pub fn csv(ranks: &[Stats]) -> String {
+    let mut out = String::from("rank,meals,hungry_total_us,hungry_max_us,forks_sent,forks_received,requests_deferred,messages_sent,messages_per_meal\n");
+    for (rank, stats) in ranks.iter().enumerate() {
+        let counters: Vec<String> = stats.counters().iter().map(u64::to_string).collect();
+        writeln!(out, "{},{},{:.3}", rank, counters.join(","), stats.messages_per_meal()).unwrap();
+    }
+    out
+}
+
+/// A JSON array with one object per rank, one per line.
+pub fn json(ranks: &[Stats]) -> String {
+    let objects: Vec<String> = ranks.iter().enumerate()
+        .map(|(rank, stats)| format!(
+            "{{\"rank\":{},\"meals\":{},\"hungry_total_us\":{},\"hungry_max_us\":{},\"forks_sent\":{},\"forks_received\":{},\"requests_deferred\":{},\"messages_sent\":{},\"messages_per_meal\":{:.3}}}",
+            rank, stats.meals, stats.hungry_total_us, stats.hungry_max_us, stats.forks_sent,
+            stats.forks_received, stats.requests_deferred, stats.messages_sent, stats.messages_per_meal()
+        ))
+        .collect();
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+/// Prints the summary table of a finished run, and writes the statistics to `--stats-file` too if
+/// one was given.
+pub fn report(ranks: &[Stats], config: &Config) -> Result<(), PhilosopherError> {
+    print!("{}", table(ranks));
+    if let Some(path) = &config.stats_file {
+        let contents = match config.stats_format {
+            StatsFormat::Csv => csv(ranks),
+            StatsFormat::Json => json(ranks),
+        };
+        fs::write(path, contents).map_err(|err| PhilosopherError::Stats { path: path.clone(), err })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranks() -> Vec<Stats> {
+        let mut first = Stats { forks_sent: 3, forks_received: 2, requests_deferred: 1, messages_sent: 7, ..Stats::default() };
+        first.ate_after(Duration::from_micros(1500));
+        first.ate_after(Duration::from_micros(500));
+        let second = Stats { forks_sent: 2, forks_received: 3, messages_sent: 4, ..Stats::default() };
+        vec![first, second]
+    }
+
+    #[test]
+    fn counters_survive_the_round_trip_and_add_up() {
+        let ranks = ranks();
+        assert_eq!(ranks[0].counters(), [2, 2000, 1500, 3, 2, 1, 7]);
+        assert_eq!(Stats::from_counters(&ranks[0].counters()), ranks[0]);
+
+        let total = Stats::total(&ranks);
+        assert_eq!(total.counters(), [2, 2000, 1500, 5, 5, 1, 11]);
+        assert_eq!(total.hungry_mean_us(), 1000);
+        assert_eq!(total.messages_per_meal(), 5.5);
+        assert_eq!(ranks[1].hungry_mean_us(), 0);
+        assert_eq!(ranks[1].messages_per_meal(), 0.0);
+    }
+
+    #[test]
+    fn every_format_has_a_line_per_rank() {
+        let ranks = ranks();
+        let table = table(&ranks);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()), "columns are not aligned:\n{}", table);
+        assert!(lines[3].trim_start().starts_with("all"));
+
+        assert_eq!(csv(&ranks).lines().nth(1), Some("0,2,2000,1500,3,2,1,7,3.500"));
+        assert_eq!(json(&ranks).lines().nth(2), Some(
+            "{\"rank\":1,\"meals\":0,\"hungry_total_us\":0,\"hungry_max_us\":0,\"forks_sent\":2,\"forks_received\":3,\"requests_deferred\":0,\"messages_sent\":4,\"messages_per_meal\":0.000}"
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{ clock::Clock, error::PhilosopherError, event::EventLog, topology::Graph, transport::Transport, wire::Envelope, Phase, Philosopher };
+use rand::RngCore;
+use std::{ fmt, str::FromStr };
+
+mod chandy_misra;
+mod drinking;
+mod resource_order;
+mod token_ring;
+mod waiter;
+
+pub use chandy_misra::ChandyMisra;
+pub use drinking::Drinking;
+pub use resource_order::ResourceOrder;
+pub use token_ring::TokenRing;
+pub use waiter::Waiter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrategyKind {
+    ChandyMisra,
+    ResourceOrder,
+    Waiter,
+    TokenRing,
+    Drinking,
+}
+
+impl FromStr for StrategyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chandy-misra" => Ok(StrategyKind::ChandyMisra),
+            "resource-order" => Ok(StrategyKind::ResourceOrder),
+            "waiter" => Ok(StrategyKind::Waiter),
+            "token-ring" => Ok(StrategyKind::TokenRing),
+            "drinking" => Ok(StrategyKind::Drinking),
+            _ => Err(format!("invalid strategy => {}", s)),
+        }
+    }
+}
+
+/// How a hungry Chandy–Misra philosopher asks for its missing forks: all at once, or one after the
+/// other, waiting for each before asking for the next. The other strategies decide this themselves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Acquisition {
+    Concurrent,
+    Sequential,
+}
+
+impl FromStr for Acquisition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "concurrent" => Ok(Acquisition::Concurrent),
+            "sequential" => Ok(Acquisition::Sequential),
+            _ => Err(format!("invalid acquisition => {}", s)),
+        }
+    }
+}
+
+pub fn create(kind: StrategyKind, acquisition: Acquisition, philosopher: Philosopher, graph: &Graph) -> Box<dyn DiningStrategy> {
+    match kind {
+        StrategyKind::ChandyMisra => Box::new(ChandyMisra::new(philosopher, acquisition)),
+        StrategyKind::ResourceOrder => Box::new(ResourceOrder::new(philosopher)),
+        StrategyKind::Waiter => Box::new(Waiter::new(philosopher, graph)),
+        StrategyKind::TokenRing => Box::new(TokenRing::new(philosopher)),
+        StrategyKind::Drinking => Box::new(Drinking::new(philosopher, acquisition)),
+    }
+}
+
+/// Decides when a philosopher may eat. `runner::dine` drives every strategy through the same loop:
+/// think while handling messages, `hungry`, handle messages until `can_eat`, eat, `finished_eating`,
+/// and after the last meal `done` followed by handling messages until `can_leave`. Drinking
+/// philosophers `pick_session` before they get thirsty, and their meals are drinking sessions.
+///
+/// All strategies move the same forks along the edges of the conflict graph and log through the same `EventLog`, so
+/// their runs can be compared with `analyze`.
+pub trait DiningStrategy: fmt::Debug {
+    fn philosopher(&self) -> &Philosopher;
+
+    /// A copy to explore a different future from, as the model checker does.
+    fn clone_box(&self) -> Box<dyn DiningStrategy>;
+
+    fn philosopher_mut(&mut self) -> &mut Philosopher;
+
+    fn start(&mut self, _transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher().log_initial_forks(log);
+        Ok(())
+    }
+
+    /// Where we are in the cycle the driver takes us through.
+    fn phase(&self) -> Phase {
+        self.philosopher().phase()
+    }
+
+    fn meals(&self) -> u32 {
+        self.philosopher().meals
+    }
+
+    /// The neighbours whose shared resources the current or next meal needs, all of them unless
+    /// `pick_session` narrowed them down.
+    fn needs(&self) -> Vec<i32> {
+        self.philosopher().neighbours().collect()
+    }
+
+    fn pick_session(&mut self, _rng: &mut dyn RngCore) {}
+
+    /// Every set of neighbours `pick_session` may choose, so that the model checker can try each
+    /// of them. Empty when there is nothing to choose.
+    fn sessions(&self) -> Vec<Vec<i32>> {
+        Vec::new()
+    }
+
+    /// Makes the next meal need `needs`, as if `pick_session` had chosen them.
+    fn choose_session(&mut self, _needs: &[i32]) {}
+
+    fn hungry(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError>;
+
+    fn can_eat(&self) -> bool;
+
+    fn start_eating(&mut self, _transport: &dyn Transport, _log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher_mut().start_eating()
+    }
+
+    fn finished_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError>;
+
+    fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError>;
+
+    fn done(&mut self, transport: &dyn Transport, _log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher_mut().announce_done(transport);
+        Ok(())
+    }
+
+    fn can_leave(&self) -> bool {
+        self.philosopher().all_peers_finished()
+    }
+
+    /// Forgets what only ever grows, so the model checker sees finitely many states.
+    fn forget_history(&mut self) {
+        let philosopher = self.philosopher_mut();
+        philosopher.clock = Clock::new(philosopher.size, philosopher.rank, false);
+        philosopher.next_seq = 0;
+        philosopher.meals = 0;
+        philosopher.stats = Default::default();
+    }
+}
@@ -0,0 +1,79 @@
+use super::{ Acquisition, DiningStrategy };
+use crate::{ error::PhilosopherError, event::EventLog, transport::Transport, wire::Envelope, ForkState, Phase, Philosopher };
+use std::collections::BTreeSet;
+
+/// Clean and dirty forks: a dirty fork is handed over on request, a clean one is kept until we
+/// have eaten. Missing forks are requested all at once or one at a time, see `Acquisition`.
+#[derive(Debug, Clone)]
+pub struct ChandyMisra {
+    philosopher: Philosopher,
+    acquisition: Acquisition,
+    /// The neighbours we asked for a fork that has not arrived yet.
+    awaiting: BTreeSet<i32>,
+}
+
+impl ChandyMisra {
+    pub fn new(philosopher: Philosopher, acquisition: Acquisition) -> Self {
+        Self { philosopher, acquisition, awaiting: BTreeSet::new() }
+    }
+
+    fn request_next(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        match self.acquisition {
+            Acquisition::Concurrent => {
+                let missing: Vec<_> = self.philosopher.neighbours()
+                    .filter(|neighbour| self.philosopher.fork(*neighbour) == ForkState::MISSING && !self.awaiting.contains(neighbour))
+                    .collect();
+                for neighbour in missing {
+                    self.philosopher.request_from(neighbour, transport, log);
+                    self.awaiting.insert(neighbour);
+                }
+            }
+            Acquisition::Sequential => {
+                if self.awaiting.is_empty() && self.philosopher.check_forks_missing() {
+                    self.awaiting.insert(self.philosopher.request_fork(transport, log)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DiningStrategy for ChandyMisra {
+    fn philosopher(&self) -> &Philosopher {
+        &self.philosopher
+    }
+
+    fn clone_box(&self) -> Box<dyn DiningStrategy> {
+        Box::new(self.clone())
+    }
+
+    fn philosopher_mut(&mut self) -> &mut Philosopher {
+        &mut self.philosopher
+    }
+
+    fn hungry(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.become_hungry()?;
+        self.request_next(transport, log)
+    }
+
+    fn can_eat(&self) -> bool {
+        !self.philosopher.check_forks_missing()
+    }
+
+    fn finished_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.eat()?;
+        self.philosopher.respond_to_existing_requests(transport, log);
+        Ok(())
+    }
+
+    fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        if let Some(neighbour) = self.philosopher.handle_message(envelope, sender, transport, log)? {
+            self.awaiting.remove(&neighbour);
+        }
+        // A dirty fork we held may have been given away while we were hungry.
+        if self.philosopher.phase() == Phase::Hungry {
+            self.request_next(transport, log)?;
+        }
+        Ok(())
+    }
+}
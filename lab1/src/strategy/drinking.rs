@@ -0,0 +1,243 @@
+use super::{ Acquisition, ChandyMisra, DiningStrategy };
+use crate::{ error::PhilosopherError, event::{ EventKind, EventLog }, transport::Transport, wire::Envelope, Fork, ForkState, Message, Phase, Philosopher };
+use rand::{ Rng, RngCore };
+use std::collections::{ BTreeMap, BTreeSet };
+
+/// Chandy and Misra's drinking philosophers. Every edge carries a bottle next to its fork, and
+/// each session needs only the bottles `pick_session` chose, so neighbours who need different
+/// bottles drink at the same time. Bottles travel on request like forks, clean on arrival and
+/// dirty once drunk from, and the forks underneath settle who keeps a contested one: a thirsty
+/// philosopher missing bottles gets hungry, and a needed bottle is only kept back while we drink
+/// or eat, or while it is clean and we also hold the fork on its edge. Neighbours never eat at
+/// once, so whoever eats collects its bottles, drinks and stops eating. Drinking sessions are the
+/// meals the driver sees.
+#[derive(Debug, Clone)]
+pub struct Drinking {
+    dining: ChandyMisra,
+    /// Keyed by neighbour, with the id of the fork on the same edge.
+    bottles: BTreeMap<i32, Fork>,
+    /// The neighbours whose bottles this session needs.
+    needs: BTreeSet<i32>,
+    /// Thinking while tranquil, hungry while thirsty, eating while drinking.
+    session: Phase,
+    /// The neighbours we asked for a bottle that has not arrived yet.
+    awaiting: BTreeSet<i32>,
+    drinks: u32,
+    done: bool,
+    announced: bool,
+}
+
+impl Drinking {
+    /// Bottles start out where the forks do.
+    pub fn new(philosopher: Philosopher, acquisition: Acquisition) -> Self {
+        let bottles = philosopher.forks.clone();
+        let needs = bottles.keys().copied().collect();
+        Self {
+            dining: ChandyMisra::new(philosopher, acquisition),
+            bottles,
+            needs,
+            session: Phase::Thinking,
+            awaiting: BTreeSet::new(),
+            drinks: 0,
+            done: false,
+            announced: false,
+        }
+    }
+
+    fn dining_phase(&self) -> Phase {
+        self.dining.philosopher().phase()
+    }
+
+    fn missing(&self) -> Vec<i32> {
+        self.needs.iter().copied().filter(|neighbour| self.bottles[neighbour].state == ForkState::MISSING).collect()
+    }
+
+    fn keeps(&self, neighbour: i32) -> bool {
+        let clean_with_fork = self.bottles[&neighbour].state == ForkState::CLEAN && self.dining.philosopher().fork(neighbour) != ForkState::MISSING;
+        self.needs.contains(&neighbour)
+            && (self.session == Phase::Eating || self.dining_phase() == Phase::Eating || (self.session == Phase::Hungry && clean_with_fork))
+    }
+
+    fn request_bottles(&mut self, transport: &dyn Transport, log: &mut EventLog) {
+        for neighbour in self.missing() {
+            if self.awaiting.insert(neighbour) {
+                let bottle = self.bottles[&neighbour].id;
+                let philosopher = self.dining.philosopher_mut();
+                philosopher.send(transport, neighbour, Message::THIRST(bottle));
+                log.emit_fork(&philosopher.clock, EventKind::BottleRequested, neighbour, bottle, ForkState::MISSING, ForkState::MISSING);
+            }
+        }
+    }
+
+    fn give_bottle(&mut self, neighbour: i32, transport: &dyn Transport, log: &mut EventLog) {
+        let philosopher = self.dining.philosopher_mut();
+        let bottle = self.bottles.get_mut(&neighbour).unwrap();
+        philosopher.send(transport, neighbour, Message::BOTTLE(bottle.id));
+        log.emit_fork(&philosopher.clock, EventKind::BottleGiven, neighbour, bottle.id, bottle.state, ForkState::MISSING);
+        bottle.state = ForkState::MISSING;
+        bottle.requested = false;
+    }
+
+    /// Hands over the bottles we held back for as long as we had a reason to.
+    fn answer_deferred(&mut self, transport: &dyn Transport, log: &mut EventLog) {
+        let deferred: Vec<i32> = self.bottles.iter().filter(|(_, bottle)| bottle.requested).map(|(&neighbour, _)| neighbour).collect();
+        for neighbour in deferred {
+            if !self.keeps(neighbour) {
+                self.give_bottle(neighbour, transport, log);
+            }
+        }
+    }
+
+    /// Gets hungry while thirsty and missing bottles, eats as soon as the forks are in but only for
+    /// as long as we are thirsty, and announces that we are done once the forks are no longer needed.
+    fn progress(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.answer_deferred(transport, log);
+        if self.session == Phase::Hungry && !self.missing().is_empty() && self.dining_phase() == Phase::Thinking {
+            self.dining.hungry(transport, log)?;
+        }
+        if self.dining_phase() == Phase::Hungry && self.dining.can_eat() {
+            self.dining.philosopher_mut().start_eating()?;
+        }
+        if self.dining_phase() == Phase::Eating && self.session != Phase::Hungry {
+            self.dining.finished_eating(transport, log)?;
+            self.answer_deferred(transport, log);
+        }
+        if self.session == Phase::Hungry {
+            self.request_bottles(transport, log);
+        }
+        if self.done && !self.announced && self.dining_phase() == Phase::Thinking {
+            self.announced = true;
+            self.dining.philosopher_mut().announce_done(transport);
+        }
+        Ok(())
+    }
+}
+
+impl DiningStrategy for Drinking {
+    fn philosopher(&self) -> &Philosopher {
+        self.dining.philosopher()
+    }
+
+    fn clone_box(&self) -> Box<dyn DiningStrategy> {
+        Box::new(self.clone())
+    }
+
+    fn philosopher_mut(&mut self) -> &mut Philosopher {
+        self.dining.philosopher_mut()
+    }
+
+    fn phase(&self) -> Phase {
+        self.session
+    }
+
+    fn meals(&self) -> u32 {
+        self.drinks
+    }
+
+    fn needs(&self) -> Vec<i32> {
+        self.needs.iter().copied().collect()
+    }
+
+    /// Every bottle with even odds, but at least one.
+    fn pick_session(&mut self, rng: &mut dyn RngCore) {
+        let neighbours: Vec<i32> = self.bottles.keys().copied().collect();
+        self.needs = neighbours.iter().copied().filter(|_| rng.random_bool(0.5)).collect();
+        if self.needs.is_empty() && !neighbours.is_empty() {
+            self.needs.insert(neighbours[rng.random_range(0..neighbours.len())]);
+        }
+    }
+
+    /// Every non-empty set of bottles.
+    fn sessions(&self) -> Vec<Vec<i32>> {
+        let neighbours: Vec<i32> = self.bottles.keys().copied().collect();
+        (1..1u32 << neighbours.len())
+            .map(|set| neighbours.iter().enumerate().filter(|&(bit, _)| set & 1 << bit != 0).map(|(_, &neighbour)| neighbour).collect())
+            .collect()
+    }
+
+    fn choose_session(&mut self, needs: &[i32]) {
+        self.needs = needs.iter().copied().collect();
+    }
+
+    fn hungry(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        if self.session != Phase::Thinking {
+            return Err(PhilosopherError::IllegalTransition { from: self.session, to: Phase::Hungry });
+        }
+        self.session = Phase::Hungry;
+        self.progress(transport, log)
+    }
+
+    fn can_eat(&self) -> bool {
+        self.session == Phase::Hungry && self.missing().is_empty()
+    }
+
+    fn start_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        let missing = self.missing();
+        if self.session == Phase::Hungry && !missing.is_empty() {
+            return Err(PhilosopherError::EatingWithoutForks { missing });
+        }
+        if self.session != Phase::Hungry {
+            return Err(PhilosopherError::IllegalTransition { from: self.session, to: Phase::Eating });
+        }
+        self.session = Phase::Eating;
+        self.progress(transport, log)
+    }
+
+    fn finished_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        if self.session != Phase::Eating {
+            return Err(PhilosopherError::IllegalTransition { from: self.session, to: Phase::Thinking });
+        }
+        self.session = Phase::Thinking;
+        self.drinks += 1;
+        for neighbour in &self.needs {
+            self.bottles.get_mut(neighbour).unwrap().state = ForkState::DIRTY;
+        }
+        self.progress(transport, log)
+    }
+
+    fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        match envelope.message {
+            Message::THIRST(bottle) => {
+                let philosopher = self.dining.philosopher_mut();
+                philosopher.clock.on_receive(&envelope.stamp);
+                let neighbour = philosopher.neighbour_of(bottle, sender)?;
+                // The bottle only ever moves on request, so whoever asks for it knows we hold it.
+                if self.bottles[&neighbour].state == ForkState::MISSING {
+                    return Err(PhilosopherError::UnexpectedMessage { context: "holding no bottle", message: envelope.message.clone(), sender });
+                }
+                if self.keeps(neighbour) {
+                    self.bottles.get_mut(&neighbour).unwrap().requested = true;
+                } else {
+                    self.give_bottle(neighbour, transport, log);
+                }
+            }
+            Message::BOTTLE(bottle) => {
+                let philosopher = self.dining.philosopher_mut();
+                philosopher.clock.on_receive(&envelope.stamp);
+                let neighbour = philosopher.neighbour_of(bottle, sender)?;
+                let shared = self.bottles.get_mut(&neighbour).unwrap();
+                log.emit_fork(&philosopher.clock, EventKind::BottleReceived, neighbour, bottle, shared.state, ForkState::CLEAN);
+                shared.state = ForkState::CLEAN;
+                self.awaiting.remove(&neighbour);
+            }
+            _ => self.dining.handle_message(envelope, sender, transport, log)?,
+        }
+        self.progress(transport, log)
+    }
+
+    /// Waits with the announcement until the forks underneath are back to thinking, so nobody
+    /// leaves while we still wait for one of their forks.
+    fn done(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.done = true;
+        self.progress(transport, log)
+    }
+
+    fn can_leave(&self) -> bool {
+        self.announced && self.philosopher().all_peers_finished()
+    }
+
+    fn forget_history(&mut self) {
+        self.dining.forget_history();
+        self.drinks = 0;
+    }
+}
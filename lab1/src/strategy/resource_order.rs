@@ -0,0 +1,95 @@
+use super::DiningStrategy;
+use crate::{ error::PhilosopherError, event::EventLog, transport::Transport, wire::Envelope, ForkState, Message, Phase, Philosopher };
+
+/// Resource ordering: a hungry philosopher acquires its forks one after the other in ascending
+/// order of their ids. A fork is kept on request once it has been acquired in that order, that is
+/// while every lower numbered fork is held too; any other fork is handed over straight away.
+#[derive(Debug, Clone)]
+pub struct ResourceOrder {
+    philosopher: Philosopher,
+    awaiting: Option<i32>,
+}
+
+impl ResourceOrder {
+    pub fn new(philosopher: Philosopher) -> Self {
+        Self { philosopher, awaiting: None }
+    }
+
+    /// Our neighbours in the order their forks are acquired.
+    fn order(&self) -> Vec<i32> {
+        let mut neighbours: Vec<_> = self.philosopher.neighbours().collect();
+        neighbours.sort_by_key(|&neighbour| self.philosopher.fork_id(neighbour));
+        neighbours
+    }
+
+    fn acquired(&self, neighbour: i32) -> bool {
+        let fork = self.philosopher.fork_id(neighbour);
+        self.philosopher.phase() != Phase::Thinking && self.philosopher.neighbours()
+            .filter(|&other| self.philosopher.fork_id(other) < fork)
+            .all(|other| self.philosopher.fork(other) != ForkState::MISSING)
+    }
+
+    fn request_next(&mut self, transport: &dyn Transport, log: &mut EventLog) {
+        if self.awaiting.is_some() {
+            return;
+        }
+        if let Some(neighbour) = self.order().into_iter().find(|&neighbour| self.philosopher.fork(neighbour) == ForkState::MISSING) {
+            self.philosopher.request_from(neighbour, transport, log);
+            self.awaiting = Some(neighbour);
+        }
+    }
+}
+
+impl DiningStrategy for ResourceOrder {
+    fn philosopher(&self) -> &Philosopher {
+        &self.philosopher
+    }
+
+    fn clone_box(&self) -> Box<dyn DiningStrategy> {
+        Box::new(self.clone())
+    }
+
+    fn philosopher_mut(&mut self) -> &mut Philosopher {
+        &mut self.philosopher
+    }
+
+    fn hungry(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.become_hungry()?;
+        self.request_next(transport, log);
+        Ok(())
+    }
+
+    fn can_eat(&self) -> bool {
+        !self.philosopher.check_forks_missing()
+    }
+
+    fn finished_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.eat()?;
+        self.philosopher.respond_to_existing_requests(transport, log);
+        Ok(())
+    }
+
+    fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.clock.on_receive(&envelope.stamp);
+        match &envelope.message {
+            msg @ Message::GIVE(_) => {
+                let neighbour = self.philosopher.received_fork(msg, sender, log)?;
+                if self.awaiting == Some(neighbour) {
+                    self.awaiting = None;
+                }
+            }
+            Message::REQUEST(fork) => {
+                let neighbour = self.philosopher.neighbour_of(*fork, sender)?;
+                let keep = self.acquired(neighbour);
+                self.philosopher.give_or_defer(neighbour, keep, transport, log);
+            }
+            Message::DONE => self.philosopher.peer_done(sender, log),
+            message => return Err(PhilosopherError::UnexpectedMessage { context: "acquiring forks in order", message: message.clone(), sender }),
+        }
+        // A higher fork may have been given away while we were still waiting for a lower one.
+        if self.philosopher.phase() == Phase::Hungry {
+            self.request_next(transport, log);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use super::DiningStrategy;
+use crate::{ error::PhilosopherError, event::EventLog, transport::Transport, wire::Envelope, Message, Phase, Philosopher };
+
+/// A single token travels from rank to rank in order, starting at rank 0, whatever the conflict
+/// graph looks like, and only its holder may eat. A hungry holder collects its missing forks,
+/// which nobody else can be using, eats and passes the token on.
+///
+/// Ranks that are done keep passing the token along. Once rank 0 is done and has heard that every
+/// other rank is too, it sends a final token around instead, after which no message can reach a
+/// rank any more and everyone may leave.
+#[derive(Debug, Clone)]
+pub struct TokenRing {
+    philosopher: Philosopher,
+    has_token: bool,
+    done: bool,
+    final_token_seen: bool,
+}
+
+impl TokenRing {
+    pub fn new(philosopher: Philosopher) -> Self {
+        let has_token = philosopher.rank == 0;
+        Self { philosopher, has_token, done: false, final_token_seen: false }
+    }
+
+    fn next(&self) -> i32 {
+        (self.philosopher.rank + 1) % self.philosopher.size
+    }
+
+    fn pass_token(&mut self, transport: &dyn Transport) {
+        self.has_token = false;
+        let next = self.next();
+        if self.philosopher.rank == 0 && self.done && self.philosopher.all_peers_finished() {
+            self.philosopher.send(transport, next, Message::FINAL);
+        } else {
+            self.philosopher.send(transport, next, Message::TOKEN);
+        }
+    }
+}
+
+impl DiningStrategy for TokenRing {
+    fn philosopher(&self) -> &Philosopher {
+        &self.philosopher
+    }
+
+    fn clone_box(&self) -> Box<dyn DiningStrategy> {
+        Box::new(self.clone())
+    }
+
+    fn philosopher_mut(&mut self) -> &mut Philosopher {
+        &mut self.philosopher
+    }
+
+    fn start(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.log_initial_forks(log);
+        if self.has_token {
+            self.pass_token(transport);
+        }
+        Ok(())
+    }
+
+    fn hungry(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.become_hungry()?;
+        if self.has_token {
+            self.philosopher.request_missing_forks(transport, log);
+        }
+        Ok(())
+    }
+
+    fn can_eat(&self) -> bool {
+        self.philosopher.phase() == Phase::Hungry && self.has_token && !self.philosopher.check_forks_missing()
+    }
+
+    fn finished_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.eat()?;
+        self.philosopher.respond_to_existing_requests(transport, log);
+        self.pass_token(transport);
+        Ok(())
+    }
+
+    fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.clock.on_receive(&envelope.stamp);
+        match &envelope.message {
+            msg @ Message::GIVE(_) => {
+                self.philosopher.received_fork(msg, sender, log)?;
+            }
+            Message::REQUEST(fork) => {
+                let neighbour = self.philosopher.neighbour_of(*fork, sender)?;
+                let keep = self.philosopher.phase() != Phase::Thinking && self.has_token;
+                self.philosopher.give_or_defer(neighbour, keep, transport, log);
+            }
+            Message::DONE => self.philosopher.peer_done(sender, log),
+            Message::TOKEN => {
+                self.has_token = true;
+                if self.philosopher.phase() == Phase::Hungry {
+                    self.philosopher.request_missing_forks(transport, log);
+                } else {
+                    self.pass_token(transport);
+                }
+            }
+            Message::FINAL => {
+                self.final_token_seen = true;
+                if self.philosopher.rank != 0 {
+                    let next = self.next();
+                    self.philosopher.send(transport, next, Message::FINAL);
+                }
+            }
+            message => return Err(PhilosopherError::UnexpectedMessage { context: "passing the token", message: message.clone(), sender }),
+        }
+        Ok(())
+    }
+
+    fn done(&mut self, transport: &dyn Transport, _log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.done = true;
+        self.philosopher.announce_done(transport);
+        Ok(())
+    }
+
+    fn can_leave(&self) -> bool {
+        self.philosopher.all_peers_finished() && self.final_token_seen
+    }
+}
@@ -0,0 +1,160 @@
+use super::DiningStrategy;
+use crate::{ error::PhilosopherError, event::EventLog, topology::Graph, transport::Transport, wire::Envelope, Message, Philosopher };
+use std::collections::VecDeque;
+
+const WAITER: i32 = 0;
+
+/// A central waiter on rank 0 seats hungry philosophers, never two neighbours at once. A seated
+/// philosopher collects its missing forks from neighbours, who cannot be eating and so hand them
+/// over straight away, and gives the seat back after eating. Rank 0 also eats and asks its own
+/// waiter directly.
+#[derive(Debug, Clone)]
+pub struct Waiter {
+    philosopher: Philosopher,
+    seated: bool,
+    table: Option<Table>,
+}
+
+/// The waiter's view of the table.
+#[derive(Debug, Clone)]
+struct Table {
+    graph: Graph,
+    seated: Vec<bool>,
+    waiting: VecDeque<i32>,
+}
+
+impl Table {
+    fn new(graph: &Graph) -> Self {
+        Self { graph: graph.clone(), seated: vec![false; graph.size() as usize], waiting: VecDeque::new() }
+    }
+
+    fn ask(&mut self, rank: i32) -> Vec<i32> {
+        self.waiting.push_back(rank);
+        self.seat()
+    }
+
+    fn release(&mut self, rank: i32) -> Vec<i32> {
+        self.seated[rank as usize] = false;
+        self.seat()
+    }
+
+    /// Seats everyone in the queue whose neighbours are neither seated nor waiting in front of
+    /// them, so nobody can be overtaken forever.
+    fn seat(&mut self) -> Vec<i32> {
+        let mut granted = Vec::new();
+        let mut ahead = vec![false; self.graph.size() as usize];
+
+        self.waiting.retain(|&rank| {
+            let neighbours = self.graph.neighbours(rank);
+            if neighbours.iter().all(|&neighbour| !self.seated[neighbour as usize] && !ahead[neighbour as usize]) {
+                self.seated[rank as usize] = true;
+                granted.push(rank);
+                false
+            } else {
+                ahead[rank as usize] = true;
+                true
+            }
+        });
+        granted
+    }
+}
+
+impl Waiter {
+    pub fn new(philosopher: Philosopher, graph: &Graph) -> Self {
+        let table = (philosopher.rank == WAITER).then(|| Table::new(graph));
+        Self { philosopher, seated: false, table }
+    }
+
+    fn table(&mut self, sender: i32, message: &Message) -> Result<&mut Table, PhilosopherError> {
+        self.table.as_mut().ok_or(PhilosopherError::UnexpectedMessage { context: "not being the waiter", message: message.clone(), sender })
+    }
+
+    fn grant(&mut self, granted: Vec<i32>, transport: &dyn Transport, log: &mut EventLog) {
+        for rank in granted {
+            if rank == self.philosopher.rank {
+                self.take_seat(transport, log);
+            } else {
+                self.philosopher.send(transport, rank, Message::GRANT);
+            }
+        }
+    }
+
+    fn take_seat(&mut self, transport: &dyn Transport, log: &mut EventLog) {
+        self.seated = true;
+        self.philosopher.request_missing_forks(transport, log);
+    }
+}
+
+impl DiningStrategy for Waiter {
+    fn philosopher(&self) -> &Philosopher {
+        &self.philosopher
+    }
+
+    fn clone_box(&self) -> Box<dyn DiningStrategy> {
+        Box::new(self.clone())
+    }
+
+    fn philosopher_mut(&mut self) -> &mut Philosopher {
+        &mut self.philosopher
+    }
+
+    fn hungry(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.become_hungry()?;
+        let rank = self.philosopher.rank;
+        match self.table.as_mut() {
+            Some(table) => {
+                let granted = table.ask(rank);
+                self.grant(granted, transport, log);
+            }
+            None => self.philosopher.send(transport, WAITER, Message::ASK),
+        }
+        Ok(())
+    }
+
+    fn can_eat(&self) -> bool {
+        self.seated && !self.philosopher.check_forks_missing()
+    }
+
+    fn finished_eating(&mut self, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.seated = false;
+        self.philosopher.eat()?;
+        self.philosopher.respond_to_existing_requests(transport, log);
+
+        let rank = self.philosopher.rank;
+        match self.table.as_mut() {
+            Some(table) => {
+                let granted = table.release(rank);
+                self.grant(granted, transport, log);
+            }
+            None => self.philosopher.send(transport, WAITER, Message::RELEASE),
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, envelope: &Envelope, sender: i32, transport: &dyn Transport, log: &mut EventLog) -> Result<(), PhilosopherError> {
+        self.philosopher.clock.on_receive(&envelope.stamp);
+        match &envelope.message {
+            msg @ Message::GIVE(_) => {
+                self.philosopher.received_fork(msg, sender, log)?;
+            }
+            Message::REQUEST(fork) => {
+                // Only a seated neighbour asks, and then we cannot be seated ourselves.
+                let neighbour = self.philosopher.neighbour_of(*fork, sender)?;
+                let keep = self.seated;
+                self.philosopher.give_or_defer(neighbour, keep, transport, log);
+            }
+            Message::DONE => self.philosopher.peer_done(sender, log),
+            msg @ Message::ASK => {
+                let granted = self.table(sender, msg)?.ask(sender);
+                self.grant(granted, transport, log);
+            }
+            msg @ Message::RELEASE => {
+                let granted = self.table(sender, msg)?.release(sender);
+                self.grant(granted, transport, log);
+            }
+            Message::GRANT => self.take_seat(transport, log),
+            message => return Err(PhilosopherError::UnexpectedMessage { context: "waiting for a seat", message: message.clone(), sender }),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{ error::PhilosopherError, ForkId };
+use std::{ fmt, fs, str::FromStr };
+
+/// Who shares a fork with whom, before the number of philosophers is known.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topology {
+    Ring,
+    Grid { rows: i32, cols: i32 },
+    Complete,
+    /// Rank 0 in the middle, sharing a fork with everyone else.
+    Star,
+    /// One `a b` pair of ranks per line of the file.
+    Edges(String),
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid topology => {}", s);
+        match s.split_once(':') {
+            None if s == "ring" => Ok(Topology::Ring),
+            None if s == "complete" => Ok(Topology::Complete),
+            None if s == "star" => Ok(Topology::Star),
+            Some(("grid", shape)) => {
+                let (rows, cols) = shape.split_once('x').ok_or_else(invalid)?;
+                let (rows, cols) = (rows.parse().map_err(|_| invalid())?, cols.parse().map_err(|_| invalid())?);
+                if rows < 1 || cols < 1 {
+                    return Err(invalid());
+                }
+                Ok(Topology::Grid { rows, cols })
+            }
+            Some(("edges", path)) if !path.is_empty() => Ok(Topology::Edges(path.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Topology::Ring => write!(f, "ring"),
+            Topology::Grid { rows, cols } => write!(f, "grid:{}x{}", rows, cols),
+            Topology::Complete => write!(f, "complete"),
+            Topology::Star => write!(f, "star"),
+            Topology::Edges(path) => write!(f, "edges:{}", path),
+        }
+    }
+}
+
+impl Topology {
+    pub fn graph(&self, size: i32) -> Result<Graph, String> {
+        let edges: Vec<(i32, i32)> = match self {
+            Topology::Ring => (0..size).map(|rank| (rank, (rank + 1) % size)).collect(),
+            Topology::Grid { rows, cols } => {
+                if rows * cols != size {
+                    return Err(format!("a {}x{} grid needs {} philosophers, not {}", rows, cols, rows * cols, size));
+                }
+                let mut edges = Vec::new();
+                for rank in 0..size {
+                    if rank % cols + 1 < *cols {
+                        edges.push((rank, rank + 1));
+                    }
+                    if rank + cols < size {
+                        edges.push((rank, rank + cols));
+                    }
+                }
+                edges
+            }
+            Topology::Complete => (0..size).flat_map(|a| (a + 1..size).map(move |b| (a, b))).collect(),
+            Topology::Star => (1..size).map(|rank| (0, rank)).collect(),
+            Topology::Edges(path) => read_edges(path)?,
+        };
+        Graph::new(size, edges)
+    }
+}
+
+fn read_edges(path: &str) -> Result<Vec<(i32, i32)>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("cannot read {} => {}", path, err))?;
+    let mut edges = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let ranks: Vec<_> = line.split_whitespace().map(|rank| rank.parse::<i32>()).collect();
+        match ranks[..] {
+            [Ok(a), Ok(b)] => edges.push((a, b)),
+            _ => return Err(format!("{}:{}: expected two ranks", path, number + 1)),
+        }
+    }
+    Ok(edges)
+}
+
+/// A conflict graph on ranks `0..size`. Every edge is a fork shared by its two ends, and its
+/// position in `edges` is the fork's id. Priorities decide who goes first at the start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graph {
+    size: i32,
+    edges: Vec<(i32, i32)>,
+    priority: Vec<i64>,
+}
+
+impl Graph {
+    /// Ignores the order of the ends and repeated edges, so a ring of two has a single fork.
+    pub fn new(size: i32, edges: impl IntoIterator<Item = (i32, i32)>) -> Result<Self, String> {
+        let mut normalized = Vec::new();
+        for (a, b) in edges {
+            if a == b || !(0..size).contains(&a) || !(0..size).contains(&b) {
+                return Err(format!("invalid edge {} {} between {} philosophers", a, b, size));
+            }
+            normalized.push((a.min(b), a.max(b)));
+        }
+        normalized.sort_unstable();
+        normalized.dedup();
+        Ok(Self { size, edges: normalized, priority: (0..size).map(i64::from).collect() })
+    }
+
+    /// Replaces the rank ordering with one priority per rank.
+    pub fn with_priority(self, priority: Vec<i64>) -> Result<Self, String> {
+        if priority.len() != self.size as usize {
+            return Err(format!("got {} priorities for {} philosophers", priority.len(), self.size));
+        }
+        Ok(Self { priority, ..self })
+    }
+
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    pub fn edges(&self) -> &[(i32, i32)] {
+        &self.edges
+    }
+
+    /// The forks of `rank` as `(neighbour, fork)` pairs, ordered by neighbour.
+    pub fn forks(&self, rank: i32) -> Vec<(i32, ForkId)> {
+        let mut forks: Vec<_> = self.edges.iter().zip(0..)
+            .filter_map(|(&(a, b), fork)| if a == rank { Some((b, fork)) } else if b == rank { Some((a, fork)) } else { None })
+            .collect();
+        forks.sort_unstable();
+        forks
+    }
+
+    pub fn neighbours(&self, rank: i32) -> Vec<i32> {
+        self.forks(rank).into_iter().map(|(neighbour, _)| neighbour).collect()
+    }
+
+    pub fn fork(&self, a: i32, b: i32) -> Option<ForkId> {
+        self.edges.iter().position(|&edge| edge == (a.min(b), a.max(b))).map(|fork| fork as ForkId)
+    }
+
+    /// Which end of `fork` holds it, dirty, when the run starts: the one with the lower priority,
+    /// or on a tie the lower rank. The holder has to yield the fork, so every fork points from the
+    /// end that yields to the end that goes first. Since priorities and ranks order all
+    /// philosophers, that precedence graph is acyclic and the table cannot start out deadlocked.
+    pub fn initial_holder(&self, fork: ForkId) -> i32 {
+        let (a, b) = self.edges[fork as usize];
+        if (self.priority[a as usize], a) < (self.priority[b as usize], b) { a } else { b }
+    }
+
+    /// Checks the forks every rank says it holds at the start, rank after rank with one flag per
+    /// fork: each fork has to be held by exactly one of its two ends, and the precedence graph
+    /// they give must not have a cycle, or the philosophers on it could wait for each other forever.
+    pub fn check_initial_forks(&self, held: &[bool]) -> Result<(), PhilosopherError> {
+        let forks = self.edges.len();
+        // Who each rank yields to, following the forks it holds.
+        let mut yields_to = vec![Vec::new(); self.size as usize];
+        for (&(a, b), fork) in self.edges.iter().zip(0..) {
+            let owners: Vec<i32> = (0..self.size).filter(|&rank| held[rank as usize * forks + fork as usize]).collect();
+            match owners[..] {
+                [owner] if owner == a => yields_to[a as usize].push(b),
+                [owner] if owner == b => yields_to[b as usize].push(a),
+                _ => return Err(PhilosopherError::ForkOwners { fork, owners }),
+            }
+        }
+
+        // Depth first, with the ranks on the current path in `path`, so a back edge is a cycle.
+        let mut visited = vec![false; self.size as usize];
+        for start in 0..self.size {
+            if visited[start as usize] {
+                continue;
+            }
+            visited[start as usize] = true;
+            let mut path = vec![start];
+            let mut next = vec![0];
+            while let Some(&rank) = path.last() {
+                let index = next.last_mut().unwrap();
+                match yields_to[rank as usize].get(*index) {
+                    Some(&neighbour) => {
+                        *index += 1;
+                        if let Some(position) = path.iter().position(|&on_path| on_path == neighbour) {
+                            return Err(PhilosopherError::PrecedenceCycle(path[position..].to_vec()));
+                        }
+                        if !visited[neighbour as usize] {
+                            visited[neighbour as usize] = true;
+                            path.push(neighbour);
+                            next.push(0);
+                        }
+                    }
+                    None => {
+                        path.pop();
+                        next.pop();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ clock::Clock, Philosopher };
+    use rand::{ rngs::StdRng, Rng, SeedableRng };
+
+    fn held_forks(graph: &Graph) -> Vec<bool> {
+        (0..graph.size()).flat_map(|rank| Philosopher::new(graph, rank, Clock::new(graph.size(), rank, false)).held_forks(graph)).collect()
+    }
+
+    #[test]
+    fn initial_forks_follow_the_priorities_and_never_form_a_cycle() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let topologies = [Topology::Ring, Topology::Complete, Topology::Star, Topology::Grid { rows: 2, cols: 3 }];
+
+        for _ in 0..200 {
+            let topology = &topologies[rng.random_range(0..topologies.len())];
+            let size = if let Topology::Grid { rows, cols } = topology { rows * cols } else { rng.random_range(2..=8) };
+            // Few distinct values, so that ties have to be broken by rank.
+            let priority: Vec<i64> = (0..size).map(|_| rng.random_range(0..3)).collect();
+            let graph = topology.graph(size).unwrap().with_priority(priority.clone()).unwrap();
+
+            for (&(a, b), fork) in graph.edges().iter().zip(0..) {
+                let expected = if (priority[a as usize], a) < (priority[b as usize], b) { a } else { b };
+                assert_eq!(graph.initial_holder(fork), expected, "fork {} with priorities {:?}", fork, priority);
+            }
+            if let Err(err) = graph.check_initial_forks(&held_forks(&graph)) {
+                panic!("{} of {} with priorities {:?} => {}", topology, size, priority, err);
+            }
+        }
+    }
+
+    #[test]
+    fn the_highest_priority_starts_without_forks() {
+        let graph = Topology::Star.graph(4).unwrap();
+        assert_eq!(held_forks(&graph)[..3], [true, true, true], "by rank the centre holds every fork");
+
+        let graph = graph.with_priority(vec![9, 0, 0, 0]).unwrap();
+        assert_eq!(held_forks(&graph)[..3], [false, false, false]);
+        assert!(graph.check_initial_forks(&held_forks(&graph)).is_ok());
+
+        assert!(Topology::Star.graph(4).unwrap().with_priority(vec![1, 2]).is_err());
+    }
+
+    #[test]
+    fn check_initial_forks_rejects_shared_missing_and_cyclic_assignments() {
+        // Fork 0 joins [0] and [1], fork 1 joins [0] and [2], fork 2 joins [1] and [2].
+        let graph = Topology::Ring.graph(3).unwrap();
+        let assignment = |forks: [[bool; 3]; 3]| forks.concat();
+
+        let both = assignment([[true, true, false], [true, false, true], [false, false, false]]);
+        assert!(matches!(graph.check_initial_forks(&both), Err(PhilosopherError::ForkOwners { fork: 0, owners }) if owners == [0, 1]));
+
+        let neither = assignment([[false, true, false], [false, false, true], [false, false, false]]);
+        assert!(matches!(graph.check_initial_forks(&neither), Err(PhilosopherError::ForkOwners { fork: 0, owners }) if owners.is_empty()));
+
+        let stranger = assignment([[false, true, false], [false, false, true], [true, false, false]]);
+        assert!(matches!(graph.check_initial_forks(&stranger), Err(PhilosopherError::ForkOwners { fork: 0, owners }) if owners == [2]));
+
+        // Everyone holds the fork towards its next rank, so everyone yields to the next one.
+        let cycle = assignment([[true, false, false], [false, false, true], [false, true, false]]);
+        assert!(matches!(graph.check_initial_forks(&cycle), Err(PhilosopherError::PrecedenceCycle(ranks)) if ranks == [0, 1, 2]));
+
+        assert!(graph.check_initial_forks(&held_forks(&graph)).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+#[cfg(feature = "mpi")]
+use mpi::{ topology::SimpleCommunicator, traits::* };
+use std::{ sync::mpsc::{ channel, Receiver, RecvTimeoutError, Sender }, time::Instant };
+#[cfg(feature = "mpi")]
+use std::thread::{ self, JoinHandle };
+
+/// How philosophers reach each other. Keeping the protocol behind this trait means it can run
+/// over MPI or, without an MPI installation, over channels between threads.
+pub trait Transport {
+    fn send(&self, dest: i32, buf: &[u8]);
+
+    /// Blocks until a message arrives and returns it together with its sender.
+    fn receive(&self) -> (Vec<u8>, i32);
+
+    /// Like `receive`, but gives up at `deadline`. A message that is already waiting is returned
+    /// even if `deadline` has passed.
+    fn receive_until(&self, deadline: Instant) -> Option<(Vec<u8>, i32)>;
+}
+
+/// Tag of the protocol's messages. Other traffic on the world communicator, like the dashboard's,
+/// uses tags of its own.
+#[cfg(feature = "mpi")]
+pub const PROTOCOL: mpi::Tag = 0;
+
+/// MPI has no receive with a timeout, so a thread of its own blocks in `receive` and passes what
+/// arrives on over a channel, where waiting for a deadline is `recv_timeout`. MPI has to be
+/// initialised with `Threading::Multiple`.
+#[cfg(feature = "mpi")]
+pub struct MpiTransport<'a> {
+    world: &'a SimpleCommunicator,
+    inbox: Receiver<(Vec<u8>, i32)>,
+    receiver: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "mpi")]
+impl<'a> MpiTransport<'a> {
+    pub fn new(world: &'a SimpleCommunicator) -> Self {
+        let (sender, inbox) = channel();
+        let receiver = thread::spawn(move || {
+            // A communicator cannot be moved to another thread, but the world can be named again.
+            let world = SimpleCommunicator::world();
+            loop {
+                let (buf, status) = world.any_process().receive_vec_with_tag::<u8>(PROTOCOL);
+                // Every envelope has a header, so an empty message can only be the one `drop` sends.
+                if buf.is_empty() && status.source_rank() == world.rank() {
+                    break;
+                }
+                if sender.send((buf, status.source_rank())).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { world, inbox, receiver: Some(receiver) }
+    }
+}
+
+#[cfg(feature = "mpi")]
+impl Transport for MpiTransport<'_> {
+    fn send(&self, dest: i32, buf: &[u8]) {
+        self.world.process_at_rank(dest).send_with_tag(buf, PROTOCOL);
+    }
+
+    fn receive(&self) -> (Vec<u8>, i32) {
+        self.inbox.recv().expect("the receiving thread runs until the transport is dropped")
+    }
+
+    fn receive_until(&self, deadline: Instant) -> Option<(Vec<u8>, i32)> {
+        match self.inbox.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(message) => Some(message),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the receiving thread runs until the transport is dropped"),
+        }
+    }
+}
+
+#[cfg(feature = "mpi")]
+impl Drop for MpiTransport<'_> {
+    fn drop(&mut self) {
+        self.world.this_process().send_with_tag(&[0u8; 0][..], PROTOCOL);
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+    }
+}
+
+/// One rank's end of an in-memory table built by `channels`. Messages between a pair of ranks
+/// arrive in the order they were sent, as they do with MPI.
+pub struct ChannelTransport {
+    rank: i32,
+    peers: Vec<Sender<(i32, Vec<u8>)>>,
+    inbox: Receiver<(i32, Vec<u8>)>,
+}
+
+/// Connects `size` ranks with each other, one transport per rank.
+pub fn channels(size: i32) -> Vec<ChannelTransport> {
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..size).map(|_| channel()).unzip();
+
+    receivers.into_iter().enumerate()
+        .map(|(rank, inbox)| ChannelTransport { rank: rank as i32, peers: senders.clone(), inbox })
+        .collect()
+}
+
+impl Transport for ChannelTransport {
+    fn send(&self, dest: i32, buf: &[u8]) {
+        // A rank that has already left drops its inbox, like a finished MPI process would.
+        let _ = self.peers[dest as usize].send((self.rank, buf.to_vec()));
+    }
+
+    fn receive(&self) -> (Vec<u8>, i32) {
+        let (sender, buf) = self.inbox.recv().expect("every rank holds a sender to itself");
+        (buf, sender)
+    }
+
+    fn receive_until(&self, deadline: Instant) -> Option<(Vec<u8>, i32)> {
+        match self.inbox.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((sender, buf)) => Some((buf, sender)),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => unreachable!("every rank holds a sender to itself"),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{ clock::Stamp, Message };
+use std::fmt;
+
+/// Bumped whenever the layout below changes in a way older decoders can't skip over.
+pub const PROTOCOL_VERSION: u8 = 3;
+
+const HAS_VECTOR: u8 = 0b0000_0001;
+
+// version, type, flags, fork, sequence number, lamport time, meal count
+const HEADER_LEN: usize = 1 + 1 + 1 + 4 + 8 + 8 + 4;
+
+/// Everything that travels between two philosophers. Encoded little-endian as
+///
+/// | version u8 | type u8 | flags u8 | fork u32 | seq u64 | lamport u64 | meals u32 | [len u32 | vector u64 * len] |
+///
+/// where `fork` is zero for messages that aren't about a fork and the vector part is only present
+/// when `flags` has `HAS_VECTOR` set. Decoders ignore trailing bytes, so later versions can append
+/// fields without breaking older ranks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub version: u8,
+    pub seq: u64,
+    pub message: Message,
+    pub stamp: Stamp,
+    pub meals: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WireError {
+    Truncated { len: usize, expected: usize },
+    UnsupportedVersion(u8),
+    UnknownType(u8),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::Truncated { len, expected } => write!(f, "message of {} bytes is truncated, expected at least {}", len, expected),
+            WireError::UnsupportedVersion(version) => write!(f, "unsupported protocol version {} (this rank speaks {})", version, PROTOCOL_VERSION),
+            WireError::UnknownType(tag) => write!(f, "unknown message type {}", tag),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl Envelope {
+    pub fn new(seq: u64, message: Message, stamp: Stamp, meals: u32) -> Self {
+        Self { version: PROTOCOL_VERSION, seq, message, stamp, meals }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, fork) = match self.message {
+            Message::GIVE(fork) => (1, fork),
+            Message::REQUEST(fork) => (2, fork),
+            Message::DONE => (3, 0),
+            Message::ASK => (4, 0),
+            Message::GRANT => (5, 0),
+            Message::RELEASE => (6, 0),
+            Message::TOKEN => (7, 0),
+            Message::FINAL => (8, 0),
+            Message::THIRST(bottle) => (9, bottle),
+            Message::BOTTLE(bottle) => (10, bottle),
+        };
+        let flags = if self.stamp.vector.is_some() { HAS_VECTOR } else { 0 };
+
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+        buf.extend([self.version, tag, flags]);
+        buf.extend(fork.to_le_bytes());
+        buf.extend(self.seq.to_le_bytes());
+        buf.extend(self.stamp.lamport.to_le_bytes());
+        buf.extend(self.meals.to_le_bytes());
+        if let Some(vector) = &self.stamp.vector {
+            buf.extend((vector.len() as u32).to_le_bytes());
+            for time in vector {
+                buf.extend(time.to_le_bytes());
+            }
+        }
+        buf
+    }
+}
+
+/// Reads fixed-size little-endian fields off the front of a buffer.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let bytes = self.buf.get(self.pos..self.pos + N)
+            .ok_or(WireError::Truncated { len: self.buf.len(), expected: self.pos + N })?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, WireError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+}
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = WireError;
+
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        let mut reader = Reader { buf, pos: 0 };
+
+        let version = reader.u8()?;
+        if version != PROTOCOL_VERSION {
+            return Err(WireError::UnsupportedVersion(version));
+        }
+        let tag = reader.u8()?;
+        let flags = reader.u8()?;
+        let fork = reader.u32()?;
+        let message = match tag {
+            1 => Message::GIVE(fork),
+            2 => Message::REQUEST(fork),
+            3 => Message::DONE,
+            4 => Message::ASK,
+            5 => Message::GRANT,
+            6 => Message::RELEASE,
+            7 => Message::TOKEN,
+            8 => Message::FINAL,
+            9 => Message::THIRST(fork),
+            10 => Message::BOTTLE(fork),
+            _ => return Err(WireError::UnknownType(tag)),
+        };
+        let seq = reader.u64()?;
+        let lamport = reader.u64()?;
+        let meals = reader.u32()?;
+
+        let vector = if flags & HAS_VECTOR != 0 {
+            let len = reader.u32()?;
+            Some((0..len).map(|_| reader.u64()).collect::<Result<Vec<_>, _>>()?)
+        } else {
+            None
+        };
+
+        Ok(Envelope { version, seq, message, stamp: Stamp { lamport, vector }, meals })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: [Message; 10] = [
+        Message::GIVE(7), Message::REQUEST(7), Message::DONE, Message::ASK, Message::GRANT,
+        Message::RELEASE, Message::TOKEN, Message::FINAL, Message::THIRST(3), Message::BOTTLE(3),
+    ];
+
+    fn envelopes() -> Vec<Envelope> {
+        let mut envelopes = Vec::new();
+        for message in MESSAGES {
+            for vector in [None, Some(vec![4, 0, 1 << 40])] {
+                envelopes.push(Envelope::new(1 << 33, message.clone(), Stamp { lamport: u64::MAX, vector }, 12));
+            }
+        }
+        envelopes
+    }
+
+    #[test]
+    fn every_message_survives_the_round_trip() {
+        for envelope in envelopes() {
+            assert_eq!(Envelope::try_from(&envelope.encode()[..]), Ok(envelope));
+        }
+    }
+
+    #[test]
+    fn a_message_cut_short_anywhere_is_truncated() {
+        for envelope in envelopes() {
+            let buf = envelope.encode();
+            for len in 0..buf.len() {
+                match Envelope::try_from(&buf[..len]) {
+                    Err(WireError::Truncated { len: seen, expected }) => assert!(seen == len && expected > len, "{:?} cut at {}", envelope.message, len),
+                    other => panic!("{:?} cut at {} => {:?}", envelope.message, len, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_versions_and_unknown_types_are_rejected() {
+        let buf = Envelope::new(0, Message::DONE, Stamp { lamport: 0, vector: None }, 0).encode();
+        for version in [0, PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+            let mut other = buf.clone();
+            other[0] = version;
+            assert_eq!(Envelope::try_from(&other[..]), Err(WireError::UnsupportedVersion(version)));
+        }
+        for tag in [0, 11, u8::MAX] {
+            let mut unknown = buf.clone();
+            unknown[1] = tag;
+            assert_eq!(Envelope::try_from(&unknown[..]), Err(WireError::UnknownType(tag)));
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        for envelope in envelopes() {
+            let mut buf = envelope.encode();
+            buf.extend([0xff; 9]);
+            assert_eq!(Envelope::try_from(&buf[..]), Ok(envelope));
+        }
+    }
+}